@@ -1,4 +1,6 @@
-PRAGMA locking_mode = EXCLUSIVE;
+PRAGMA busy_timeout = 5000;
+-- This only applies to new databases until Database::vacuum converts existing ones
+PRAGMA auto_vacuum = INCREMENTAL;
 PRAGMA journal_mode = WAL;
 PRAGMA synchronous = NORMAL;
 
@@ -11,4 +13,65 @@ CREATE TABLE IF NOT EXISTS listener_avgs (
     utc_12 INT,
     utc_16 INT,
     utc_20 INT
-);
\ No newline at end of file
+);
+CREATE TABLE IF NOT EXISTS feed_stats (
+    id INTEGER NOT NULL PRIMARY KEY,
+    last_seen TIMESTAMP NOT NULL,
+    samples TEXT NOT NULL,
+    average REAL NOT NULL,
+    last_average REAL NOT NULL,
+    unskewed_average REAL,
+    spike_count INT NOT NULL,
+    escalation REAL
+);
+
+CREATE TABLE IF NOT EXISTS last_notifs (
+    id INTEGER NOT NULL PRIMARY KEY,
+    time TIMESTAMP NOT NULL,
+    jump REAL NOT NULL,
+    alert TEXT
+);
+
+CREATE TABLE IF NOT EXISTS feed_mutes (
+    id INTEGER NOT NULL PRIMARY KEY,
+    until TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS notif_history (
+    id INTEGER NOT NULL PRIMARY KEY,
+    time TIMESTAMP NOT NULL,
+    kind TEXT NOT NULL,
+    message TEXT,
+    feed_id INTEGER,
+    name TEXT,
+    county TEXT,
+    location TEXT,
+    listeners INT,
+    jump REAL,
+    baseline REAL,
+    average REAL,
+    unskewed_average REAL,
+    spike_count INT,
+    alert TEXT
+);
+
+CREATE INDEX IF NOT EXISTS notif_history_time ON notif_history (time);
+
+CREATE TABLE IF NOT EXISTS listener_samples (
+    id INTEGER NOT NULL PRIMARY KEY,
+    time TIMESTAMP NOT NULL,
+    feed_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    county TEXT NOT NULL,
+    location TEXT NOT NULL,
+    listeners INT NOT NULL,
+    average REAL NOT NULL,
+    unskewed_average REAL,
+    jump REAL NOT NULL,
+    has_spiked BOOLEAN NOT NULL,
+    spike_count INT NOT NULL,
+    alert TEXT,
+    notified BOOLEAN NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS listener_samples_time ON listener_samples (time);
@@ -0,0 +1,751 @@
+use super::{
+    Config, DatabaseOptions, EscalationOptions, FeedOptions, FeedSelector, FilterOptions,
+    LogOptions, MetricsOptions, MiscOptions, NotifMode, NotifOptions, QuietHours, SortOptions,
+};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use std::collections::HashMap;
+use std::fmt;
+use toml::value::Table;
+use toml::Value;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found in a config file.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub level: Level,
+    /// The line the problem is on, starting from 1.
+    ///
+    /// Errors from the TOML parser already include their line and column in the message, so this is only set for problems we find ourselves.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.level, self.message)?;
+
+        if let Some(line) = self.line {
+            write!(f, " at line {}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the contents of a config file for errors, as well as settings that are valid but likely to be mistakes.
+///
+/// County selectors are compared against `known_counties`. No county warnings are given if it's empty.
+pub fn check(contents: &str, known_counties: &[String]) -> Vec<Diagnostic> {
+    let value = match toml::from_str::<Value>(contents) {
+        Ok(value) => value,
+        Err(err) => {
+            return vec![Diagnostic {
+                level: Level::Error,
+                line: None,
+                message: err.to_string(),
+            }]
+        }
+    };
+
+    let mut checker = Checker {
+        key_lines: parse_key_lines(contents),
+        known_counties,
+        diagnostics: Vec::new(),
+    };
+
+    if let Some(root) = value.as_table() {
+        checker.check_tables(root);
+    }
+
+    match toml::from_str::<Config>(contents) {
+        Ok(config) => checker.check_thresholds(&config),
+        Err(err) => checker.diagnostics.push(Diagnostic {
+            level: Level::Error,
+            line: None,
+            message: err.to_string(),
+        }),
+    }
+
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|diag| (diag.line.is_some(), diag.line));
+    diagnostics
+}
+
+struct Checker<'a> {
+    key_lines: HashMap<Vec<String>, usize>,
+    known_counties: &'a [String],
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn push<S>(&mut self, level: Level, path: &[&str], message: S)
+    where
+        S: Into<String>,
+    {
+        let path = path.iter().map(|&key| key.to_string()).collect::<Vec<_>>();
+
+        self.diagnostics.push(Diagnostic {
+            level,
+            line: self.key_lines.get(&path).copied(),
+            message: message.into(),
+        });
+    }
+
+    fn check_tables(&mut self, root: &Table) {
+        self.check_unknown_keys(&[], root, fields_of::<Config>());
+
+        for (name, value) in root {
+            let table = match value.as_table() {
+                Some(table) => table,
+                None => continue,
+            };
+
+            let fields = match name.as_str() {
+                "feed" => {
+                    self.check_feed_options(&["feed"], table);
+                    continue;
+                }
+                "weekday" => {
+                    for (day, options) in table {
+                        if let Some(options) = options.as_table() {
+                            self.check_feed_options(&["weekday", day], options);
+                            self.check_weekday_shadowing(day, options, root.get("feed"));
+                        }
+                    }
+
+                    continue;
+                }
+                "filters" => {
+                    self.check_filters(table);
+                    fields_of::<FilterOptions>()
+                }
+                "misc" => fields_of::<MiscOptions>(),
+                "sorting" => fields_of::<SortOptions>(),
+                "quiet_hours" => fields_of::<QuietHours>(),
+                "escalation" => fields_of::<EscalationOptions>(),
+                "notification" => fields_of::<NotifOptions>(),
+                "log" => fields_of::<LogOptions>(),
+                "metrics" => fields_of::<MetricsOptions>(),
+                "database" => fields_of::<DatabaseOptions>(),
+                _ => continue,
+            };
+
+            self.check_unknown_keys(&[name], table, fields);
+        }
+    }
+
+    fn check_unknown_keys(&mut self, path: &[&str], table: &Table, expected: &[&str]) {
+        for key in table.keys() {
+            if expected.contains(&key.as_str()) {
+                continue;
+            }
+
+            let mut key_path = path.to_vec();
+            key_path.push(key);
+
+            let message = format!(
+                "unknown key `{}`, expected one of: {}",
+                key_path.join("."),
+                expected.join(", ")
+            );
+
+            self.push(Level::Warning, &key_path, message);
+        }
+    }
+
+    fn check_feed_options(&mut self, path: &[&str], selectors: &Table) {
+        let mut seen: Vec<(FeedSelector, &str)> = Vec::new();
+
+        for (raw, options) in selectors {
+            let mut key_path = path.to_vec();
+            key_path.push(raw);
+
+            // Invalid selectors are reported when the whole config is decoded
+            let selector = match FeedSelector::parse(raw) {
+                Some(selector) => selector,
+                None => continue,
+            };
+
+            if let FeedSelector::County(county) = &selector {
+                self.check_county(&key_path, county);
+            }
+
+            // Only the options of one matching selector are used, and which one isn't defined
+            for (other, other_raw) in &seen {
+                if !can_overlap(&selector, other) {
+                    continue;
+                }
+
+                let message = format!(
+                    "selectors `{}` and `{}` in [{}] can both match the same feed, so which one's options are used for it is arbitrary",
+                    other_raw,
+                    raw,
+                    path.join(".")
+                );
+
+                self.push(Level::Warning, &key_path, message);
+            }
+
+            seen.push((selector, raw));
+
+            if let Some(table) = options.as_table() {
+                self.check_unknown_keys(&key_path, table, fields_of::<FeedOptions>());
+            }
+
+            if let Ok(options) = FeedOptions::deserialize(options.clone()) {
+                self.check_jump_required(
+                    &key_path,
+                    "jump_required",
+                    options.jump_required.as_mult(),
+                );
+                self.check_jump_required(
+                    &key_path,
+                    "jump_required_set_unskewed",
+                    options.jump_required_unskewed.as_mult(),
+                );
+            }
+        }
+    }
+
+    fn check_jump_required(&mut self, path: &[&str], key: &str, mult: f32) {
+        let mut key_path = path.to_vec();
+        key_path.push(key);
+
+        let message = if mult <= 0.0 {
+            format!(
+                "`{}` is {}%, so every feed will be shown on each update",
+                key_path.join("."),
+                mult * 100.0
+            )
+        } else if mult > 10.0 {
+            format!(
+                "`{}` is {}%, which feeds are unlikely to ever reach",
+                key_path.join("."),
+                mult * 100.0
+            )
+        } else {
+            return;
+        };
+
+        self.push(Level::Warning, &key_path, message);
+    }
+
+    /// Selectors for a weekday replace every selector in [feed] on that day, rather than being merged with them.
+    fn check_weekday_shadowing(&mut self, day: &str, options: &Table, feed: Option<&Value>) {
+        let feed = match feed.and_then(Value::as_table) {
+            Some(feed) => feed,
+            None => return,
+        };
+
+        let shadowed = feed
+            .keys()
+            .filter(|raw| !options.contains_key(*raw))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+
+        if shadowed.is_empty() {
+            return;
+        }
+
+        let message = format!(
+            "[weekday.{}] replaces every selector in [feed] on that day, so these are not applied then: {}",
+            day,
+            shadowed.join(", ")
+        );
+
+        self.push(Level::Warning, &["weekday", day], message);
+    }
+
+    fn check_filters(&mut self, filters: &Table) {
+        let list = |name| -> Vec<(&str, FeedSelector)> {
+            filters
+                .get(name)
+                .and_then(Value::as_array)
+                .map(|entries| {
+                    entries
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .filter_map(|raw| FeedSelector::parse(raw).map(|sel| (raw, sel)))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let whitelist = list("whitelist");
+        let blacklist = list("blacklist");
+
+        for (name, entries) in &[("whitelist", &whitelist), ("blacklist", &blacklist)] {
+            for (_, selector) in entries.iter() {
+                if let FeedSelector::County(county) = selector {
+                    self.check_county(&["filters", name], county);
+                }
+            }
+        }
+
+        if blacklist
+            .iter()
+            .any(|(_, sel)| *sel == FeedSelector::Global)
+        {
+            self.push(
+                Level::Warning,
+                &["filters", "blacklist"],
+                "`global` is blacklisted, so every feed will be filtered out",
+            );
+        }
+
+        for (raw, selector) in &whitelist {
+            if blacklist
+                .iter()
+                .any(|(_, other)| same_selector(selector, other))
+            {
+                let message = format!("`{}` is in both the whitelist and blacklist", raw);
+                self.push(Level::Warning, &["filters", "blacklist"], message);
+            }
+        }
+    }
+
+    fn check_county(&mut self, path: &[&str], county: &str) {
+        if self.known_counties.is_empty() {
+            return;
+        }
+
+        let is_known = self
+            .known_counties
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(county));
+
+        if is_known {
+            return;
+        }
+
+        let message = format!(
+            "no feed has been seen in county \"{}\", so it may be misspelled",
+            county
+        );
+
+        self.push(Level::Warning, path, message);
+    }
+
+    fn check_thresholds(&mut self, config: &Config) {
+        let misc = &config.misc;
+
+        if misc.update_time_mins <= 0.0 {
+            self.push(
+                Level::Error,
+                &["misc", "update_time_mins"],
+                "`misc.update_time_mins` must be greater than 0",
+            );
+        } else if misc.update_time_mins < 1.0 {
+            self.push(
+                Level::Warning,
+                &["misc", "update_time_mins"],
+                "`misc.update_time_mins` is less than a minute, which puts unnecessary load on Broadcastify",
+            );
+        }
+
+        match misc.min_update_time_mins {
+            Some(min) if min <= 0.0 => self.push(
+                Level::Error,
+                &["misc", "min_update_time_mins"],
+                "`misc.min_update_time_mins` must be greater than 0",
+            ),
+            Some(min) if min > misc.update_time_mins => self.push(
+                Level::Warning,
+                &["misc", "min_update_time_mins"],
+                "`misc.min_update_time_mins` is greater than `misc.update_time_mins`, so updates won't speed up during spikes",
+            ),
+            _ => (),
+        }
+
+        match misc.max_update_time_mins {
+            Some(max) if max < misc.update_time_mins => self.push(
+                Level::Warning,
+                &["misc", "max_update_time_mins"],
+                "`misc.max_update_time_mins` is less than `misc.update_time_mins`, so updates won't slow down when feeds are quiet",
+            ),
+            _ => (),
+        }
+
+        if misc.update_jitter_secs as f32 > misc.update_time_mins * 30.0 {
+            self.push(
+                Level::Warning,
+                &["misc", "update_jitter_secs"],
+                "`misc.update_jitter_secs` is more than half of `misc.update_time_mins`, so it will be capped to half of it",
+            );
+        }
+
+        if misc.show_max == 0 {
+            self.push(
+                Level::Warning,
+                &["misc", "show_max"],
+                "`misc.show_max` is 0, so no notifications will be shown",
+            );
+        }
+
+        if misc.notify_cooldown_mins < 0.0 {
+            self.push(
+                Level::Warning,
+                &["misc", "notify_cooldown_mins"],
+                "`misc.notify_cooldown_mins` is negative and will be treated as no cooldown",
+            );
+        }
+
+        if misc.source_timeout_secs == 0 {
+            self.push(
+                Level::Error,
+                &["misc", "source_timeout_secs"],
+                "`misc.source_timeout_secs` must be greater than 0",
+            );
+        }
+
+        if misc.update_deadline_secs == 0 {
+            self.push(
+                Level::Error,
+                &["misc", "update_deadline_secs"],
+                "`misc.update_deadline_secs` must be greater than 0",
+            );
+        } else if misc.update_deadline_secs < misc.source_timeout_secs {
+            self.push(
+                Level::Warning,
+                &["misc", "update_deadline_secs"],
+                "`misc.update_deadline_secs` is less than `misc.source_timeout_secs`, so slow sources will fail the whole update before they time out",
+            );
+        }
+
+        if misc.renotify_jump_required.as_mult() < 0.0 {
+            self.push(
+                Level::Warning,
+                &["misc", "renotify_jump_required"],
+                "`misc.renotify_jump_required` is negative, so feeds will be shown again on every update",
+            );
+        }
+
+        let notif = &config.notification;
+
+        if notif.critical_jump.as_mult() <= 0.0 {
+            self.push(
+                Level::Warning,
+                &["notification", "critical_jump"],
+                "`notification.critical_jump` is 0% or less, so every notification will be critical",
+            );
+        }
+
+        if notif.mute_hours == 0 {
+            self.push(
+                Level::Warning,
+                &["notification", "mute_hours"],
+                "`notification.mute_hours` is 0, so muting a feed will have no effect",
+            );
+        }
+
+        if notif.mode == NotifMode::Digest && notif.digest_max_lines == 0 {
+            self.push(
+                Level::Warning,
+                &["notification", "digest_max_lines"],
+                "`notification.digest_max_lines` is 0, so digests will not list any feeds",
+            );
+        }
+
+        let multiples = &config.escalation.multiples;
+
+        if multiples.iter().any(|&mult| mult <= 1.0) {
+            self.push(
+                Level::Warning,
+                &["escalation", "multiples"],
+                "`escalation.multiples` contains values of 1 or less, which every spiking feed reaches immediately",
+            );
+        }
+
+        if multiples.windows(2).any(|pair| pair[0] >= pair[1]) {
+            self.push(
+                Level::Warning,
+                &["escalation", "multiples"],
+                "`escalation.multiples` should be in ascending order",
+            );
+        }
+
+        let quiet = &config.quiet_hours;
+
+        let has_empty_range = quiet
+            .times
+            .iter()
+            .chain(quiet.weekday.values().flatten())
+            .any(|range| range.start == range.end);
+
+        if has_empty_range {
+            self.push(
+                Level::Warning,
+                &["quiet_hours"],
+                "[quiet_hours] contains a time range that starts and ends at the same time, so it will never be active",
+            );
+        }
+
+        if let Some(jump) = quiet.break_through_jump {
+            if jump.as_mult() <= 0.0 {
+                self.push(
+                    Level::Warning,
+                    &["quiet_hours", "break_through_jump"],
+                    "`quiet_hours.break_through_jump` is 0% or less, so quiet hours will never hold anything back",
+                );
+            }
+        }
+
+        if let Some(addr) = config.metrics.listen {
+            if !addr.ip().is_loopback() {
+                self.push(
+                    Level::Warning,
+                    &["metrics", "listen"],
+                    format!(
+                        "the metrics endpoint at {} can be reached from other machines",
+                        addr
+                    ),
+                );
+            }
+        }
+
+        if config.log.max_file_size_kb == 0 {
+            self.push(
+                Level::Warning,
+                &["log", "max_file_size_kb"],
+                "`log.max_file_size_kb` is 0, so the log file will be rotated on every message",
+            );
+        }
+
+        if config.database.cleanup_interval_hours <= 0.0 {
+            self.push(
+                Level::Error,
+                &["database", "cleanup_interval_hours"],
+                "`database.cleanup_interval_hours` must be greater than 0",
+            );
+        }
+
+        let last_notif_retention_mins =
+            config.database.last_notif_retention_days as f32 * 24.0 * 60.0;
+
+        if config.database.last_notif_retention_days > 0
+            && last_notif_retention_mins < config.misc.notify_cooldown_mins
+        {
+            self.push(
+                Level::Warning,
+                &["database", "last_notif_retention_days"],
+                "`database.last_notif_retention_days` is shorter than `misc.notify_cooldown_mins`, so feeds may be shown again before their cooldown is over",
+            );
+        }
+    }
+}
+
+fn same_selector(left: &FeedSelector, right: &FeedSelector) -> bool {
+    match (left, right) {
+        (FeedSelector::County(left), FeedSelector::County(right)) => {
+            left.eq_ignore_ascii_case(right)
+        }
+        (left, right) => left == right,
+    }
+}
+
+/// Indicates whether or not there can be a feed matched by both selectors.
+///
+/// Only selectors of the same kind with different values are known to never match the same feed, since a county or ID can be in any location.
+fn can_overlap(left: &FeedSelector, right: &FeedSelector) -> bool {
+    match (left, right) {
+        (FeedSelector::ID(_), FeedSelector::ID(_))
+        | (FeedSelector::County(_), FeedSelector::County(_))
+        | (FeedSelector::Location(_), FeedSelector::Location(_)) => same_selector(left, right),
+        _ => true,
+    }
+}
+
+/// Finds the line every table header and key is defined on.
+///
+/// This doesn't handle every part of the TOML spec, such as dotted keys inside inline tables, but a missing line only makes a diagnostic less helpful.
+fn parse_key_lines(contents: &str) -> HashMap<Vec<String>, usize> {
+    let mut lines = HashMap::new();
+    let mut table = Vec::new();
+
+    for (num, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        if line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            if let Some(path) = split_key_path(line.trim_start_matches('['), ']') {
+                // Headers such as [weekday.sat.global] also define every table above them
+                for len in 1..=path.len() {
+                    lines.entry(path[..len].to_vec()).or_insert(num + 1);
+                }
+
+                table = path;
+            }
+
+            continue;
+        }
+
+        if let Some(key) = split_key_path(line, '=') {
+            let mut path = table.clone();
+            path.extend(key);
+            lines.entry(path).or_insert(num + 1);
+        }
+    }
+
+    lines
+}
+
+/// Splits a dotted key such as `feed."county(Example County)"` into its parts, stopping at `terminator`.
+///
+/// Returns None if `terminator` is never reached.
+fn split_key_path(text: &str, terminator: char) -> Option<Vec<String>> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut quote = None;
+
+    for ch in text.chars() {
+        match quote {
+            Some(open) if ch == open => quote = None,
+            Some(_) => part.push(ch),
+            None if ch == '"' || ch == '\'' => quote = Some(ch),
+            None if ch == '.' => parts.push(part.split_off(0).trim().to_string()),
+            None if ch == terminator => {
+                parts.push(part.trim().to_string());
+                return Some(parts);
+            }
+            None => part.push(ch),
+        }
+    }
+
+    None
+}
+
+/// Returns the names of the fields a struct expects when it's deserialized.
+///
+/// This lets unknown keys be found without having to keep a separate list of every option in sync.
+pub(super) fn fields_of<T>() -> &'static [&'static str]
+where
+    T: for<'de> Deserialize<'de>,
+{
+    match T::deserialize(FieldCollector) {
+        Err(FieldNames(fields)) => fields,
+        Ok(_) => &[],
+    }
+}
+
+/// A deserializer that fails as soon as it's asked for a struct, returning the names of the struct's fields.
+struct FieldCollector;
+
+#[derive(Debug)]
+struct FieldNames(&'static [&'static str]);
+
+impl fmt::Display for FieldNames {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fields: {}", self.0.join(", "))
+    }
+}
+
+impl std::error::Error for FieldNames {}
+
+impl de::Error for FieldNames {
+    fn custom<T>(_: T) -> Self
+    where
+        T: fmt::Display,
+    {
+        Self(&[])
+    }
+}
+
+impl<'de> Deserializer<'de> for FieldCollector {
+    type Error = FieldNames;
+
+    fn deserialize_any<V>(self, _: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(FieldNames(&[]))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(FieldNames(fields))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warnings(contents: &str) -> Vec<String> {
+        check(contents, &[])
+            .into_iter()
+            .filter(|diag| diag.level == Level::Warning)
+            .map(|diag| diag.message)
+            .collect()
+    }
+
+    #[test]
+    fn overlapping_selectors_are_warned_about() {
+        let overlapping = [
+            ("global", "id(1)"),
+            ("location(us-california)", "county(Sacramento County)"),
+            ("location(us-california)", "id(1)"),
+            ("county(Sacramento County)", "id(1)"),
+            ("county(Sacramento County)", "county(sacramento county)"),
+        ];
+
+        for (left, right) in &overlapping {
+            let contents = format!("[feed.\"{}\"]\n[feed.\"{}\"]\n", left, right);
+            let warnings = warnings(&contents);
+
+            assert_eq!(warnings.len(), 1, "{} and {}: {:?}", left, right, warnings);
+            assert!(warnings[0].contains("arbitrary"));
+        }
+    }
+
+    #[test]
+    fn disjoint_selectors_are_not_warned_about() {
+        let disjoint = [
+            ("id(1)", "id(2)"),
+            ("county(Sacramento County)", "county(Yolo County)"),
+            ("location(us-california)", "location(us-new-york)"),
+        ];
+
+        for (left, right) in &disjoint {
+            let contents = format!("[feed.\"{}\"]\n[feed.\"{}\"]\n", left, right);
+            let warnings = warnings(&contents);
+
+            assert!(
+                warnings.is_empty(),
+                "{} and {}: {:?}",
+                left,
+                right,
+                warnings
+            );
+        }
+    }
+}
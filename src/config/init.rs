@@ -0,0 +1,181 @@
+use super::check::fields_of;
+use super::{
+    Config, DatabaseOptions, EscalationOptions, FeedOptions, FilterOptions, LogOptions,
+    MetricsOptions, MiscOptions, NotifOptions, QuietHours, SortOptions,
+};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use toml::Value;
+
+/// Returns the contents of a config file with every option set to its default value and a comment describing each one.
+///
+/// The values are taken from the `Default` impl of each section, so they can't drift from the values actually used.
+pub fn default_config() -> Result<String> {
+    let mut contents = String::from(concat!(
+        "# Configuration for ",
+        env!("CARGO_PKG_NAME"),
+        ". Options that are commented out are not set by default.\n",
+        "# Run `",
+        env!("CARGO_PKG_NAME"),
+        " config check` after making changes to check for mistakes.\n",
+    ));
+
+    write_section(&mut contents, "feed.global", &FeedOptions::default())?;
+    write_section(&mut contents, "misc", &MiscOptions::default())?;
+    write_section(&mut contents, "sorting", &SortOptions::default())?;
+    write_section(&mut contents, "filters", &FilterOptions::default())?;
+    write_section(&mut contents, "notification", &NotifOptions::default())?;
+    write_section(&mut contents, "escalation", &EscalationOptions::default())?;
+    write_section(&mut contents, "quiet_hours", &QuietHours::default())?;
+    write_section(&mut contents, "log", &LogOptions::default())?;
+    write_section(&mut contents, "metrics", &MetricsOptions::default())?;
+    write_section(&mut contents, "database", &DatabaseOptions::default())?;
+
+    // Make sure we never write a config that can't be loaded
+    toml::from_str::<Config>(&contents).context("generated config is invalid")?;
+
+    Ok(contents)
+}
+
+fn write_section<T>(contents: &mut String, name: &str, section: &T) -> Result<()>
+where
+    T: Serialize + for<'de> Deserialize<'de>,
+{
+    let values = Value::try_from(section)
+        .with_context(|| format!("failed to encode [{}] defaults", name))?;
+
+    let section_description =
+        describe_section(name).ok_or_else(|| anyhow!("[{}] has no description", name))?;
+
+    contents.push('\n');
+    write_comment(contents, section_description);
+    writeln!(contents, "[{}]", name)?;
+
+    for &key in fields_of::<T>() {
+        let (description, example) = describe_key(name, key)
+            .ok_or_else(|| anyhow!("`{}.{}` has no description", name, key))?;
+
+        match values.get(key) {
+            // Tables are written as their own section, so use an example for them instead
+            Some(value) if !value.is_table() => {
+                write_comment(contents, description);
+                write_comment(contents, "This is the default.");
+                writeln!(contents, "{} = {}", key, value)?;
+            }
+            _ => {
+                write_comment(contents, description);
+                write_comment(contents, "This is not set by default.");
+                writeln!(contents, "# {} = {}", key, example.unwrap_or("..."))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_comment(contents: &mut String, comment: &str) {
+    for line in comment.lines() {
+        contents.push_str("# ");
+        contents.push_str(line);
+        contents.push('\n');
+    }
+}
+
+fn describe_section(name: &str) -> Option<&'static str> {
+    let description = match name {
+        "feed.global" => "The default options used by all feeds.\nOptions for specific feeds can be set with other selectors, such as [feed.\"id(123)\"], [feed.\"county(Sacramento County)\"] or [feed.\"location(us-california)\"].\nOnly one selector's options are used for a feed, and which one is arbitrary when several match it, so replace this table rather than adding selectors that overlap it.\nOptions can also be set for specific weekdays, such as [weekday.sat.global].",
+        "misc" => "This section controls how feeds are processed.",
+        "sorting" => "This section controls the order notifications are shown for feeds.",
+        "filters" => "This section allows you to blacklist and whitelist feeds, using the same selectors that are used in the feed and weekday sections.",
+        "notification" => "This section controls how notifications are shown.",
+        "escalation" => "This section controls notifications for feeds that keep growing during an incident.\nAn incident starts once a feed has spiked consistently, and ends when its listeners fall back to the count it had before.",
+        "quiet_hours" => "This section controls when notifications should be held back.",
+        "log" => "This section controls what is logged and where.",
+        "metrics" => "This section controls the Prometheus metrics endpoint.",
+        "database" => "This section controls how long data is kept in the database.",
+        _ => return None,
+    };
+
+    Some(description)
+}
+
+/// Returns a description of the specified option, as well as an example value for options that aren't set by default.
+fn describe_key(section: &str, key: &str) -> Option<(&'static str, Option<&'static str>)> {
+    let description = match (section, key) {
+        ("feed.global", "jump_required") => ("The percentage a feed's listeners must jump by in order to show it.", None),
+        ("feed.global", "jump_required_set_unskewed") => ("The percentage a feed's listeners must jump by in order to set its unskewed average.", None),
+        ("misc", "update_time_mins") => ("How often to run feed updates in minutes.\nUpdates are aligned to the clock, so a value of 6 runs them at :00, :06, :12 and so on.", None),
+        ("misc", "min_update_time_mins") => ("The update time in minutes to switch to while any feed is spiking.", Some("2")),
+        ("misc", "max_update_time_mins") => ("The update time in minutes to gradually back off to while no feeds are spiking.\nEach update without a spiking feed increases the update time by half until it reaches this value.", Some("15")),
+        ("misc", "update_jitter_secs") => ("The maximum number of seconds to randomly delay each update by, to avoid making requests at the same time as everyone else.\nThis is capped at half of update_time_mins.", None),
+        ("misc", "minimum_listeners") => ("The minimum number of listeners a feed must have to process it.", None),
+        ("misc", "process_location") => ("The location to process in addition to the top 50 feeds.", Some("\"us-california\"")),
+        ("misc", "show_max") => ("The maximum number of feeds to display an alert for at once.", None),
+        ("misc", "show_max_times") => ("The maximum number of times to show a feed that's alerting consecutively.", Some("5")),
+        ("misc", "show_alert_feeds") => ("Specifies whether or not feeds that have an alert attached to them should be shown regardless of them spiking in listeners.", None),
+        ("misc", "notify_cooldown_mins") => ("The number of minutes to wait before a spiking feed can be shown again.\nFeeds that are only shown because of an alert are only shown again once their alert changes.", None),
+        ("misc", "renotify_jump_required") => ("The percentage a feed's listener jump must grow by in order to be shown again before its cooldown is over.", None),
+        ("misc", "source_timeout_secs") => ("The number of seconds to wait for each source of feeds (the top 50 feeds and the process_location feeds) before giving up on it.", None),
+        ("misc", "update_deadline_secs") => ("The number of seconds every source has to be fetched within before the update fails.\nSources are fetched at the same time, so this only needs to be as long as the slowest one.", None),
+        ("sorting", "value") => ("The value to sort feeds by. Possible values are \"jump\" and \"listeners\".", None),
+        ("sorting", "order") => ("The order to sort the feeds in. Possible values are \"descending\" and \"ascending\".", None),
+        ("filters", "blacklist") => ("Feeds matching any of these selectors will never be shown, such as [ \"id(1)\", \"location(us-alabama)\" ].", None),
+        ("filters", "whitelist") => ("When set, only feeds matching one of these selectors will be shown.", None),
+        ("notification", "critical_jump") => ("The percentage a feed must jump by in order to show its notification with critical urgency.\nFeeds that have spiked with an alert attached or have escalated are always shown with critical urgency.", None),
+        ("notification", "mute_hours") => ("The number of hours a feed will not be shown for after choosing to mute it.", None),
+        ("notification", "title") => ("The template for the notification title.", None),
+        ("notification", "body") => ("The template for the notification body.", None),
+        ("notification", "mode") => ("Specifies how feeds from an update should be shown. Possible values are \"individual\" and \"digest\".\nThe \"digest\" value means that every feed from an update is shown in a single notification, with a line for each feed.", None),
+        ("notification", "digest_line") => ("The template for each feed's line in a digest notification. This is also used for the quiet hours summary.", None),
+        ("notification", "digest_max_lines") => ("The maximum number of feeds to list in a digest notification. The quiet hours summary always lists every feed.", None),
+        ("escalation", "multiples") => ("The multiples of a feed's listener count before an incident that will show a notification once reached.", None),
+        ("escalation", "notify_subsided") => ("Specifies whether or not a notification should be shown once an escalated feed falls back to its listener count before the incident.", None),
+        ("quiet_hours", "mode") => ("Specifies what to do with notifications during quiet hours. Possible values are \"queue\" and \"suppress\".\nThe \"queue\" value means that a summary of every held back feed will be shown when quiet hours end.", None),
+        ("quiet_hours", "times") => ("The time ranges to hold back notifications on every day, such as [ \"22:00-07:00\" ].", None),
+        ("quiet_hours", "weekday") => ("Overrides the time ranges used on specific weekdays.", Some("{ sat = [ \"23:00-09:00\" ] }")),
+        ("quiet_hours", "break_through_alerts") => ("Specifies whether or not feeds that have an alert attached to them should be shown during quiet hours.", None),
+        ("quiet_hours", "break_through_jump") => ("The percentage a feed must jump by in order to be shown during quiet hours.", Some("500")),
+        ("log", "level") => ("The minimum level of messages to log. Possible values are \"off\", \"error\", \"warn\", \"info\", \"debug\" and \"trace\".\nThe \"debug\" level explains why each feed was or wasn't shown.", None),
+        ("log", "output") => ("Where to write log messages to. Possible values are \"stderr\", \"journal\" and \"file\".", None),
+        ("log", "file") => ("The file to log to when the output is \"file\". Defaults to bcnotif.log in the local data directory.", Some("\"/tmp/bcnotif.log\"")),
+        ("log", "max_file_size_kb") => ("The size in kilobytes the log file can reach before it's rotated.", None),
+        ("log", "max_files") => ("The number of rotated log files to keep.", None),
+        ("metrics", "listen") => ("The address to serve metrics on at /metrics. Changes to this option require a restart.", Some("\"127.0.0.1:9477\"")),
+        ("database", "listener_avg_retention_days") => ("The number of days to keep the listener averages and statistics of a feed after it was last seen. A value of 0 keeps them forever.\nKeeping them longer preserves the averages of feeds that are only active during part of the year.", None),
+        ("database", "sample_retention_days") => ("The number of days to keep the listener samples recorded on each update. A value of 0 keeps them forever.", None),
+        ("database", "notif_history_retention_days") => ("The number of days to keep the history of shown notifications and errors used by the recent command. A value of 0 keeps it forever.", None),
+        ("database", "last_notif_retention_days") => ("The number of days to remember the last notification shown for each feed, which cooldowns and repeated alerts are checked against. A value of 0 keeps them forever.", None),
+        ("database", "cleanup_interval_hours") => ("How often to remove old data from the database in hours.", None),
+        ("database", "vacuum") => ("Specifies whether or not space freed by removing old data should be given back to the system.", None),
+        _ => return None,
+    };
+
+    Some(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_option_is_described() {
+        // Writing the config fails if any section or option is missing a description
+        default_config().unwrap();
+    }
+
+    #[test]
+    fn every_section_is_written() {
+        let contents = default_config().unwrap();
+        let value = toml::from_str::<Value>(&contents).unwrap();
+
+        for &section in fields_of::<Config>() {
+            // Weekday options are the same as the ones in [feed.global]
+            if section == "weekday" {
+                continue;
+            }
+
+            assert!(value.get(section).is_some(), "[{}] is not written", section);
+        }
+    }
+}
@@ -0,0 +1,925 @@
+// Used by the `bcnotif config` commands rather than being part of the library's API
+#[doc(hidden)]
+pub mod check;
+#[doc(hidden)]
+pub mod init;
+
+use crate::err;
+use crate::feed::{Feed, FeedNotif, Location};
+use crate::path::FilePath;
+use crate::template::Template;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Datelike, Local, NaiveTime, Weekday};
+use log::error;
+use serde::de::Visitor;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::result;
+use std::str::FromStr;
+use std::thread;
+use strum_macros::EnumString;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub weekday: HashMap<Weekday, FeedOptionMap>,
+    #[serde(default)]
+    pub feed: FeedOptionMap,
+    #[serde(default)]
+    pub misc: MiscOptions,
+    #[serde(default)]
+    pub sorting: SortOptions,
+    #[serde(default)]
+    pub filters: FilterOptions,
+    #[serde(default)]
+    pub quiet_hours: QuietHours,
+    #[serde(default)]
+    pub escalation: EscalationOptions,
+    #[serde(default)]
+    pub notification: NotifOptions,
+    #[serde(default)]
+    pub log: LogOptions,
+    #[serde(default)]
+    pub metrics: MetricsOptions,
+    #[serde(default)]
+    pub database: DatabaseOptions,
+}
+
+impl Config {
+    pub fn load_or_new() -> Result<Self> {
+        match Self::load() {
+            Ok(cfg) => Ok(cfg),
+            Err(err) if err::is_file_nonexistant(&err) => Ok(Self::default()),
+            err => err,
+        }
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::validated_path()?;
+
+        let contents = fs::read_to_string(&path)
+            .with_context(|| anyhow!("failed to load config at {}", path.display()))?;
+
+        let config = toml::from_str(&contents)
+            .with_context(|| anyhow!("failed to decode config at {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    pub fn validated_path() -> Result<PathBuf> {
+        let mut path = FilePath::Config
+            .validated_dir_path()
+            .context("failed to get config path")?;
+
+        path.push(Self::FILE_NAME);
+        Ok(path)
+    }
+
+    const FILE_NAME: &'static str = "config.toml";
+
+    /// How long to wait for the config file to stop changing before calling back.
+    /// Many editors save files by writing to them multiple times or by replacing them entirely.
+    const WATCH_DEBOUNCE_MS: i32 = 500;
+
+    /// Watches the config file for changes from a new thread and calls `on_change` after it has been modified.
+    ///
+    /// The `on_change` closure should return false when the config should no longer be watched.
+    pub fn watch<F>(on_change: F) -> Result<()>
+    where
+        F: Fn() -> bool + Send + 'static,
+    {
+        use nix::errno::Errno;
+        use nix::poll::{poll, PollFd, PollFlags};
+        use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+
+        let dir = FilePath::Config
+            .validated_dir_path()
+            .context("failed to get config path")?;
+
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC)
+            .map_err(|err| anyhow!("failed to init inotify: {}", err))?;
+
+        // The directory is watched instead of the file itself so the watch survives the file being replaced
+        inotify
+            .add_watch(
+                &dir,
+                AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO,
+            )
+            .map_err(|err| anyhow!("failed to watch {}: {}", dir.display(), err))?;
+
+        let has_config_changed = move || -> nix::Result<bool> {
+            let events = inotify.read_events()?;
+
+            Ok(events
+                .iter()
+                .any(|event| event.name.as_deref() == Some(OsStr::new(Self::FILE_NAME))))
+        };
+
+        let is_readable_within = move |timeout_ms| {
+            let mut fds = [PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN)];
+            matches!(poll(&mut fds, timeout_ms), Ok(num) if num > 0)
+        };
+
+        thread::spawn(move || loop {
+            match has_config_changed() {
+                Ok(true) => (),
+                Ok(false) | Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(err) => {
+                    // Reads that fail return immediately, so retrying would spin
+                    error!(
+                        "failed to read config file changes, it will only be reloaded on SIGHUP: {}",
+                        err
+                    );
+                    break;
+                }
+            }
+
+            while is_readable_within(Self::WATCH_DEBOUNCE_MS) {
+                inotify.read_events().ok();
+            }
+
+            if !on_change() {
+                break;
+            }
+        });
+
+        Ok(())
+    }
+
+    pub fn options_for_feed<'a>(&'a self, feed: &Feed, weekday: Weekday) -> Cow<'a, FeedOptions> {
+        let selector = match self.weekday.get(&weekday) {
+            Some(weekday_opts) => weekday_opts,
+            None => &self.feed,
+        };
+
+        selector
+            .iter()
+            .find(|(sel, _)| sel.matches_feed(feed))
+            .map_or_else(|| FeedOptions::default().into(), |(_, value)| value.into())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FeedOptions {
+    #[serde(default = "FeedOptions::jump_required_default")]
+    pub jump_required: Percentage,
+    #[serde(
+        rename = "jump_required_set_unskewed",
+        default = "FeedOptions::jump_required_unskewed_default"
+    )]
+    pub jump_required_unskewed: Percentage,
+}
+
+impl FeedOptions {
+    fn jump_required_default() -> Percentage {
+        Percentage::new(40.0)
+    }
+
+    fn jump_required_unskewed_default() -> Percentage {
+        Percentage::new(400.0)
+    }
+}
+
+impl Default for FeedOptions {
+    fn default() -> Self {
+        Self {
+            jump_required: Self::jump_required_default(),
+            jump_required_unskewed: Self::jump_required_unskewed_default(),
+        }
+    }
+}
+
+impl<'a> Into<Cow<'a, Self>> for FeedOptions {
+    fn into(self) -> Cow<'a, Self> {
+        Cow::Owned(self)
+    }
+}
+
+impl<'a> Into<Cow<'a, FeedOptions>> for &'a FeedOptions {
+    fn into(self) -> Cow<'a, FeedOptions> {
+        Cow::Borrowed(self)
+    }
+}
+
+pub type FeedOptionMap = HashMap<FeedSelector, FeedOptions>;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MiscOptions {
+    #[serde(default = "MiscOptions::update_time_mins_default")]
+    pub update_time_mins: f32,
+    pub min_update_time_mins: Option<f32>,
+    pub max_update_time_mins: Option<f32>,
+    #[serde(default)]
+    pub update_jitter_secs: u64,
+    #[serde(default = "MiscOptions::minimum_listeners_default")]
+    pub minimum_listeners: u32,
+    #[serde(rename = "process_location")]
+    pub location: Option<Location>,
+    #[serde(default = "MiscOptions::show_max_default")]
+    pub show_max: u32,
+    pub show_max_times: Option<u32>,
+    #[serde(default = "MiscOptions::show_alert_feeds_default")]
+    pub show_alert_feeds: bool,
+    #[serde(default = "MiscOptions::notify_cooldown_mins_default")]
+    pub notify_cooldown_mins: f32,
+    #[serde(default = "MiscOptions::renotify_jump_required_default")]
+    pub renotify_jump_required: Percentage,
+    #[serde(default = "MiscOptions::source_timeout_secs_default")]
+    pub source_timeout_secs: u64,
+    #[serde(default = "MiscOptions::update_deadline_secs_default")]
+    pub update_deadline_secs: u64,
+}
+
+impl MiscOptions {
+    const fn update_time_mins_default() -> f32 {
+        6.0
+    }
+
+    const fn minimum_listeners_default() -> u32 {
+        15
+    }
+
+    const fn show_max_default() -> u32 {
+        10
+    }
+
+    const fn show_alert_feeds_default() -> bool {
+        true
+    }
+
+    const fn notify_cooldown_mins_default() -> f32 {
+        30.0
+    }
+
+    fn renotify_jump_required_default() -> Percentage {
+        Percentage::new(50.0)
+    }
+
+    const fn source_timeout_secs_default() -> u64 {
+        15
+    }
+
+    const fn update_deadline_secs_default() -> u64 {
+        30
+    }
+}
+
+impl Default for MiscOptions {
+    fn default() -> Self {
+        Self {
+            update_time_mins: Self::update_time_mins_default(),
+            min_update_time_mins: None,
+            max_update_time_mins: None,
+            update_jitter_secs: 0,
+            minimum_listeners: Self::minimum_listeners_default(),
+            location: None,
+            show_max: Self::show_max_default(),
+            show_max_times: None,
+            show_alert_feeds: Self::show_alert_feeds_default(),
+            notify_cooldown_mins: Self::notify_cooldown_mins_default(),
+            renotify_jump_required: Self::renotify_jump_required_default(),
+            source_timeout_secs: Self::source_timeout_secs_default(),
+            update_deadline_secs: Self::update_deadline_secs_default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SortOptions {
+    #[serde(default)]
+    pub value: SortType,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortType {
+    Jump,
+    Listeners,
+}
+
+impl Default for SortType {
+    fn default() -> Self {
+        Self::Jump
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        Self::Descending
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct FilterOptions {
+    #[serde(default)]
+    pub blacklist: Vec<FeedSelector>,
+    #[serde(default)]
+    pub whitelist: Vec<FeedSelector>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NotifOptions {
+    #[serde(default = "NotifOptions::critical_jump_default")]
+    pub critical_jump: Percentage,
+    #[serde(default = "NotifOptions::mute_hours_default")]
+    pub mute_hours: u32,
+    #[serde(default = "NotifOptions::title_default")]
+    pub title: Template,
+    #[serde(default = "NotifOptions::body_default")]
+    pub body: Template,
+    #[serde(default)]
+    pub mode: NotifMode,
+    #[serde(default = "NotifOptions::digest_line_default")]
+    pub digest_line: Template,
+    #[serde(default = "NotifOptions::digest_max_lines_default")]
+    pub digest_max_lines: u32,
+}
+
+impl NotifOptions {
+    pub const TITLE_DEFAULT: &'static str = concat!(
+        env!("CARGO_PKG_NAME"),
+        " update {index} of {max_index}{status}"
+    );
+
+    pub const BODY_DEFAULT: &'static str = "{location} | {name}\n{listeners} (^{jump}){alert_line}";
+
+    pub const DIGEST_LINE_DEFAULT: &'static str = "{location} | {name}: {listeners} (^{jump})";
+
+    fn critical_jump_default() -> Percentage {
+        Percentage::new(300.0)
+    }
+
+    const fn mute_hours_default() -> u32 {
+        4
+    }
+
+    fn title_default() -> Template {
+        Template::parse(Self::TITLE_DEFAULT).expect("invalid default title template")
+    }
+
+    fn body_default() -> Template {
+        Template::parse(Self::BODY_DEFAULT).expect("invalid default body template")
+    }
+
+    fn digest_line_default() -> Template {
+        Template::parse(Self::DIGEST_LINE_DEFAULT).expect("invalid default digest line template")
+    }
+
+    const fn digest_max_lines_default() -> u32 {
+        10
+    }
+}
+
+impl Default for NotifOptions {
+    fn default() -> Self {
+        Self {
+            critical_jump: Self::critical_jump_default(),
+            mute_hours: Self::mute_hours_default(),
+            title: Self::title_default(),
+            body: Self::body_default(),
+            mode: NotifMode::default(),
+            digest_line: Self::digest_line_default(),
+            digest_max_lines: Self::digest_max_lines_default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifMode {
+    Individual,
+    Digest,
+}
+
+impl Default for NotifMode {
+    fn default() -> Self {
+        Self::Individual
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EscalationOptions {
+    #[serde(default = "EscalationOptions::multiples_default")]
+    pub multiples: Vec<f32>,
+    #[serde(default = "EscalationOptions::notify_subsided_default")]
+    pub notify_subsided: bool,
+}
+
+impl EscalationOptions {
+    fn multiples_default() -> Vec<f32> {
+        vec![2.0, 5.0, 10.0]
+    }
+
+    const fn notify_subsided_default() -> bool {
+        true
+    }
+}
+
+impl Default for EscalationOptions {
+    fn default() -> Self {
+        Self {
+            multiples: Self::multiples_default(),
+            notify_subsided: Self::notify_subsided_default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct QuietHours {
+    #[serde(default)]
+    pub mode: QuietMode,
+    #[serde(default)]
+    pub times: Vec<TimeRange>,
+    #[serde(default)]
+    pub weekday: HashMap<Weekday, Vec<TimeRange>>,
+    #[serde(default = "QuietHours::break_through_alerts_default")]
+    pub break_through_alerts: bool,
+    pub break_through_jump: Option<Percentage>,
+}
+
+impl QuietHours {
+    const fn break_through_alerts_default() -> bool {
+        true
+    }
+
+    fn times_for(&self, weekday: Weekday) -> &[TimeRange] {
+        match self.weekday.get(&weekday) {
+            Some(times) => times,
+            None => &self.times,
+        }
+    }
+
+    /// Returns true if the specified time falls within any quiet period.
+    ///
+    /// Periods that wrap past midnight are considered to belong to the weekday they start on.
+    pub fn is_active(&self, time: DateTime<Local>) -> bool {
+        let weekday = time.weekday();
+        let time = time.time();
+
+        let today = self
+            .times_for(weekday)
+            .iter()
+            .any(|range| range.contains_start_day(time));
+
+        let from_yesterday = self
+            .times_for(weekday.pred())
+            .iter()
+            .any(|range| range.contains_next_day(time));
+
+        today || from_yesterday
+    }
+
+    /// Returns true if the specified notification is important enough to be shown during quiet hours.
+    pub fn allows(&self, notif: &FeedNotif) -> bool {
+        if self.break_through_alerts && notif.feed.alert.is_some() {
+            return true;
+        }
+
+        match self.break_through_jump {
+            Some(required) => notif.jump_pcnt() >= required.as_mult(),
+            None => false,
+        }
+    }
+}
+
+impl Default for QuietHours {
+    fn default() -> Self {
+        Self {
+            mode: QuietMode::default(),
+            times: Vec::new(),
+            weekday: HashMap::new(),
+            break_through_alerts: Self::break_through_alerts_default(),
+            break_through_jump: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuietMode {
+    Suppress,
+    Queue,
+}
+
+impl Default for QuietMode {
+    fn default() -> Self {
+        Self::Queue
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LogOptions {
+    #[serde(default)]
+    pub level: LogLevel,
+    #[serde(default)]
+    pub output: LogOutput,
+    pub file: Option<PathBuf>,
+    #[serde(default = "LogOptions::max_file_size_kb_default")]
+    pub max_file_size_kb: u64,
+    #[serde(default = "LogOptions::max_files_default")]
+    pub max_files: u32,
+}
+
+impl LogOptions {
+    const fn max_file_size_kb_default() -> u64 {
+        1024
+    }
+
+    const fn max_files_default() -> u32 {
+        3
+    }
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            level: LogLevel::default(),
+            output: LogOutput::default(),
+            file: None,
+            max_file_size_kb: Self::max_file_size_kb_default(),
+            max_files: Self::max_files_default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, EnumString, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Default for LogLevel {
+    fn default() -> Self {
+        Self::Info
+    }
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => Self::Off,
+            LogLevel::Error => Self::Error,
+            LogLevel::Warn => Self::Warn,
+            LogLevel::Info => Self::Info,
+            LogLevel::Debug => Self::Debug,
+            LogLevel::Trace => Self::Trace,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogOutput {
+    Stderr,
+    Journal,
+    File,
+}
+
+impl Default for LogOutput {
+    fn default() -> Self {
+        Self::Stderr
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct MetricsOptions {
+    pub listen: Option<SocketAddr>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DatabaseOptions {
+    #[serde(default = "DatabaseOptions::listener_avg_retention_days_default")]
+    pub listener_avg_retention_days: u32,
+    #[serde(default = "DatabaseOptions::sample_retention_days_default")]
+    pub sample_retention_days: u32,
+    #[serde(default = "DatabaseOptions::notif_history_retention_days_default")]
+    pub notif_history_retention_days: u32,
+    #[serde(default = "DatabaseOptions::last_notif_retention_days_default")]
+    pub last_notif_retention_days: u32,
+    #[serde(default = "DatabaseOptions::cleanup_interval_hours_default")]
+    pub cleanup_interval_hours: f32,
+    #[serde(default = "DatabaseOptions::vacuum_default")]
+    pub vacuum: bool,
+}
+
+impl DatabaseOptions {
+    const fn listener_avg_retention_days_default() -> u32 {
+        30
+    }
+
+    const fn sample_retention_days_default() -> u32 {
+        30
+    }
+
+    const fn notif_history_retention_days_default() -> u32 {
+        90
+    }
+
+    const fn last_notif_retention_days_default() -> u32 {
+        30
+    }
+
+    const fn cleanup_interval_hours_default() -> f32 {
+        12.0
+    }
+
+    const fn vacuum_default() -> bool {
+        true
+    }
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        Self {
+            listener_avg_retention_days: Self::listener_avg_retention_days_default(),
+            sample_retention_days: Self::sample_retention_days_default(),
+            notif_history_retention_days: Self::notif_history_retention_days_default(),
+            last_notif_retention_days: Self::last_notif_retention_days_default(),
+            cleanup_interval_hours: Self::cleanup_interval_hours_default(),
+            vacuum: Self::vacuum_default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TimeRange {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeRange {
+    fn from_str<S>(value: S) -> Option<Self>
+    where
+        S: AsRef<str>,
+    {
+        let mut split = value.as_ref().splitn(2, '-');
+
+        let mut next_time = || NaiveTime::parse_from_str(split.next()?.trim(), "%H:%M").ok();
+        let start = next_time()?;
+        let end = next_time()?;
+
+        Some(Self { start, end })
+    }
+
+    #[inline(always)]
+    fn wraps(self) -> bool {
+        self.start > self.end
+    }
+
+    fn contains_start_day(self, time: NaiveTime) -> bool {
+        if self.wraps() {
+            time >= self.start
+        } else {
+            time >= self.start && time < self.end
+        }
+    }
+
+    fn contains_next_day(self, time: NaiveTime) -> bool {
+        self.wraps() && time < self.end
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeRange {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TimeRangeVisitor;
+
+        impl<'de> Visitor<'de> for TimeRangeVisitor {
+            type Value = TimeRange;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a 24-hour time range (HH:MM-HH:MM)")
+            }
+
+            fn visit_str<E>(self, value: &str) -> result::Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                use serde::de::{self, Unexpected};
+
+                TimeRange::from_str(value)
+                    .ok_or_else(|| de::Error::invalid_value(Unexpected::Str(value), &self))
+            }
+        }
+
+        deserializer.deserialize_str(TimeRangeVisitor)
+    }
+}
+
+impl Serialize for TimeRange {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub enum FeedSelector {
+    Global,
+    ID(u32),
+    County(String),
+    Location(Location),
+}
+
+impl FeedSelector {
+    /// Parses a selector in the form used in the config, such as `id(123)` or `location(us-california)`.
+    pub fn parse<S>(value: S) -> Option<Self>
+    where
+        S: AsRef<str>,
+    {
+        match value.as_ref() {
+            "global" => Some(Self::Global),
+            selector => {
+                if !selector.ends_with(')') {
+                    return None;
+                }
+
+                let opening_bracket = selector.find('(')?;
+                let sel_name = selector[..opening_bracket].to_ascii_lowercase();
+                let sel_value = &selector[opening_bracket + 1..selector.len() - 1];
+
+                match sel_name.as_ref() {
+                    "id" => sel_value.parse().ok().map(Self::ID),
+                    "county" => Some(Self::County(sel_value.into())),
+                    "location" => Location::from_str(sel_value).ok().map(Self::Location),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    pub fn matches_feed(&self, feed: &Feed) -> bool {
+        match self {
+            Self::Global => true,
+            Self::ID(id) => *id == feed.id,
+            Self::County(county) => county.eq_ignore_ascii_case(&feed.county),
+            Self::Location(loc) => loc.id() == feed.location.id(),
+        }
+    }
+}
+
+impl fmt::Display for FeedSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Global => write!(f, "global"),
+            Self::ID(id) => write!(f, "id({})", id),
+            Self::County(county) => write!(f, "county({})", county),
+            Self::Location(loc) => write!(f, "location({})", loc.name()),
+        }
+    }
+}
+
+impl Default for FeedSelector {
+    fn default() -> Self {
+        Self::Global
+    }
+}
+
+impl<'de> Deserialize<'de> for FeedSelector {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FeedSelectorVisitor;
+
+        impl<'de> Visitor<'de> for FeedSelectorVisitor {
+            type Value = FeedSelector;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter
+                    .write_str("a feed selector (global, id(id), county(name), location(name))")
+            }
+
+            fn visit_str<E>(self, value: &str) -> result::Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                use serde::de::{self, Unexpected};
+
+                FeedSelector::parse(value)
+                    .ok_or_else(|| de::Error::invalid_value(Unexpected::Str(value), &self))
+            }
+        }
+
+        deserializer.deserialize_str(FeedSelectorVisitor)
+    }
+}
+
+impl Serialize for FeedSelector {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Percentage(f32);
+
+impl Percentage {
+    pub fn new(pcnt: f32) -> Self {
+        Self(pcnt / 100.0)
+    }
+
+    pub fn as_mult(self) -> f32 {
+        self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for Percentage {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct PercentageVisitor;
+
+        impl<'de> Visitor<'de> for PercentageVisitor {
+            type Value = f32;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a percentage between 0 and 100")
+            }
+
+            fn visit_f32<E>(self, value: f32) -> result::Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(value / 100.0)
+            }
+
+            fn visit_f64<E>(self, value: f64) -> result::Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(value as f32 / 100.0)
+            }
+
+            fn visit_i32<E>(self, value: i32) -> result::Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(value as f32 / 100.0)
+            }
+
+            fn visit_i64<E>(self, value: i64) -> result::Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(value as f32 / 100.0)
+            }
+        }
+
+        let raw_pcnt = deserializer.deserialize_f32(PercentageVisitor)?;
+        Ok(Self(raw_pcnt))
+    }
+}
+
+impl Serialize for Percentage {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // Avoid writing values like 40.000004 due to the precision lost when converting to a multiplier
+        let pcnt = (f64::from(self.0) * 100.0 * 1000.0).round() / 1000.0;
+        serializer.serialize_f64(pcnt)
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Context, Result};
+use bcnotif::FilePath;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// A command sent to a running daemon. Each request is a single line of JSON, such as `{"cmd":"mute","id":123}`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    Update,
+    Reload,
+    Mute { id: u32, hours: Option<u32> },
+    Unmute { id: u32 },
+    Stats,
+    Shutdown,
+}
+
+impl Request {
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let arg_id = |index: usize| -> Result<u32> {
+            args.get(index)
+                .ok_or_else(|| anyhow!("missing feed id"))?
+                .parse()
+                .context("invalid feed id")
+        };
+
+        let cmd = args.first().map(String::as_str).unwrap_or("");
+
+        match cmd {
+            "update" => Ok(Self::Update),
+            "reload" => Ok(Self::Reload),
+            "mute" => {
+                let hours = match args.get(2) {
+                    Some(hours) => Some(hours.parse().context("invalid number of hours")?),
+                    None => None,
+                };
+
+                Ok(Self::Mute {
+                    id: arg_id(1)?,
+                    hours,
+                })
+            }
+            "unmute" => Ok(Self::Unmute { id: arg_id(1)? }),
+            "stats" => Ok(Self::Stats),
+            "shutdown" => Ok(Self::Shutdown),
+            "" => Err(anyhow!("missing control command")),
+            cmd => Err(anyhow!("unknown control command: {}", cmd)),
+        }
+    }
+}
+
+/// The reply to a request. Each response is a single line of JSON.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Stats { feeds: Vec<FeedState> },
+    Error { message: String },
+}
+
+impl Response {
+    pub fn from_result(result: Result<()>) -> Self {
+        match result {
+            Ok(()) => Self::Ok,
+            Err(err) => Self::Error {
+                message: format!("{:#}", err),
+            },
+        }
+    }
+}
+
+/// The in-memory listener statistics of a feed.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FeedState {
+    pub id: u32,
+    pub average: f32,
+    pub unskewed_average: Option<f32>,
+    pub jump: f32,
+    pub has_spiked: bool,
+    pub spike_count: u32,
+    pub escalation: Option<f32>,
+}
+
+pub fn socket_path() -> Result<PathBuf> {
+    let mut path = FilePath::Runtime
+        .validated_dir_path()
+        .context("failed to get runtime path")?;
+
+    path.push(concat!(env!("CARGO_PKG_NAME"), ".sock"));
+    Ok(path)
+}
+
+/// The bound control socket, which is removed when dropped.
+pub struct Socket(PathBuf);
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+/// Listens for requests on the control socket from a new thread.
+///
+/// The `handler` is called for every request received, and should return None if the daemon is shutting down.
+pub fn listen<F>(handler: F) -> Result<Socket>
+where
+    F: Fn(Request) -> Option<Response> + Send + 'static,
+{
+    let path = socket_path()?;
+
+    if path.exists() {
+        if is_daemon_running() {
+            return Err(anyhow!(
+                "another instance is already listening on {}",
+                path.display()
+            ));
+        }
+
+        // The socket was left behind by an instance that didn't exit cleanly
+        fs::remove_file(&path).context("failed to remove stale control socket")?;
+    }
+
+    let listener = UnixListener::bind(&path)
+        .with_context(|| anyhow!("failed to bind control socket at {}", path.display()))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+
+            // A misbehaving client shouldn't stop the daemon from being controlled
+            handle_client(stream, &handler).ok();
+        }
+    });
+
+    Ok(Socket(path))
+}
+
+fn handle_client<F>(stream: UnixStream, handler: &F) -> Result<()>
+where
+    F: Fn(Request) -> Option<Response>,
+{
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => match handler(request) {
+                Some(response) => response,
+                None => break,
+            },
+            Err(err) => Response::Error {
+                message: format!("invalid request: {}", err),
+            },
+        };
+
+        let mut encoded = serde_json::to_string(&response)?;
+        encoded.push('\n');
+        writer.write_all(encoded.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Returns true if a daemon is listening on the control socket.
+pub fn is_daemon_running() -> bool {
+    socket_path().map_or(false, |path| UnixStream::connect(path).is_ok())
+}
+
+/// Sends a request to a running daemon and waits for its response.
+pub fn send(request: &Request) -> Result<Response> {
+    let path = socket_path()?;
+
+    let mut stream = UnixStream::connect(&path)
+        .with_context(|| anyhow!("failed to connect to daemon at {}", path.display()))?;
+
+    let mut encoded = serde_json::to_string(request)?;
+    encoded.push('\n');
+    stream.write_all(encoded.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .context("failed to read response from daemon")?;
+
+    serde_json::from_str(&line).context("failed to decode response from daemon")
+}
+
+pub fn remove_socket() {
+    if let Ok(path) = socket_path() {
+        fs::remove_file(path).ok();
+    }
+}
@@ -1,8 +1,10 @@
 use crate::path::FilePath;
 use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
 use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
-use std::path::PathBuf;
+use diesel::sql_types::Integer;
+use std::path::{Path, PathBuf};
 
 table! {
     listener_avgs {
@@ -17,11 +19,84 @@ table! {
     }
 }
 
+table! {
+    feed_stats {
+        id -> Integer,
+        last_seen -> BigInt,
+        samples -> Text,
+        average -> Float,
+        last_average -> Float,
+        unskewed_average -> Nullable<Float>,
+        spike_count -> Integer,
+        escalation -> Nullable<Float>,
+    }
+}
+
+table! {
+    last_notifs {
+        id -> Integer,
+        time -> BigInt,
+        jump -> Float,
+        alert -> Nullable<Text>,
+    }
+}
+
+table! {
+    feed_mutes {
+        id -> Integer,
+        until -> BigInt,
+    }
+}
+
+table! {
+    notif_history {
+        id -> Integer,
+        time -> BigInt,
+        kind -> Text,
+        message -> Nullable<Text>,
+        feed_id -> Nullable<Integer>,
+        name -> Nullable<Text>,
+        county -> Nullable<Text>,
+        location -> Nullable<Text>,
+        listeners -> Nullable<Integer>,
+        jump -> Nullable<Float>,
+        baseline -> Nullable<Float>,
+        average -> Nullable<Float>,
+        unskewed_average -> Nullable<Float>,
+        spike_count -> Nullable<Integer>,
+        alert -> Nullable<Text>,
+    }
+}
+
+table! {
+    listener_samples {
+        id -> Integer,
+        time -> BigInt,
+        feed_id -> Integer,
+        name -> Text,
+        county -> Text,
+        location -> Text,
+        listeners -> Integer,
+        average -> Float,
+        unskewed_average -> Nullable<Float>,
+        jump -> Float,
+        has_spiked -> Bool,
+        spike_count -> Integer,
+        alert -> Nullable<Text>,
+        notified -> Bool,
+    }
+}
+
 pub struct Database(SqliteConnection);
 
 impl Database {
     pub fn open() -> Result<Self> {
         let path = Self::validated_path().context("getting database path failed")?;
+        Self::open_at(&path)
+    }
+
+    /// Opens the database at `path` instead of the one in the local data directory, creating it if needed.
+    pub fn open_at(path: &Path) -> Result<Self> {
         let conn = SqliteConnection::establish(&path.to_string_lossy())
             .context("opening database connection failed")?;
 
@@ -44,6 +119,38 @@ impl Database {
     pub fn conn(&self) -> &SqliteConnection {
         &self.0
     }
+
+    /// Gives the space left behind by removed rows back to the system.
+    ///
+    /// Databases created before incremental vacuuming was enabled are rebuilt once to enable it, which can take a while
+    /// for large databases. Every vacuum after that only frees unused pages.
+    pub fn vacuum(&self) -> QueryResult<()> {
+        const INCREMENTAL: i32 = 2;
+
+        let mode = diesel::sql_query("PRAGMA auto_vacuum").get_result::<AutoVacuum>(self.conn())?;
+
+        if mode.auto_vacuum == INCREMENTAL {
+            self.conn().batch_execute("PRAGMA incremental_vacuum")
+        } else {
+            // The schema already asked for incremental vacuuming, which a full vacuum applies
+            self.conn().batch_execute("VACUUM")
+        }
+    }
+}
+
+/// Returns the timestamp of the time `days` ago, which rows older than are removed.
+///
+/// Retention periods too long to represent keep every row.
+pub fn retention_cutoff(days: u32) -> i64 {
+    Utc::now()
+        .checked_sub_signed(Duration::days(days.into()))
+        .map_or(i64::MIN, |time| time.timestamp())
+}
+
+#[derive(QueryableByName)]
+struct AutoVacuum {
+    #[sql_type = "Integer"]
+    auto_vacuum: i32,
 }
 
 impl Drop for Database {
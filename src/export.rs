@@ -0,0 +1,246 @@
+use crate::config::FeedSelector;
+use crate::database::Database;
+use crate::feed::stats::ListenerAvg;
+use crate::history::ListenerSample;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+use strum_macros::EnumString;
+
+#[derive(Copy, Clone, Debug, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum ExportFormat {
+    /// A header line with the name of each column, followed by a line for each record.
+    Csv,
+    /// An array with an object for each record.
+    Json,
+}
+
+/// A row of data that can be exported.
+pub trait Record: Serialize {
+    /// The name of each column in CSV output.
+    const COLUMNS: &'static [&'static str];
+
+    /// Returns the value of each column in CSV output, in the same order as `COLUMNS`.
+    fn csv_fields(&self) -> Vec<String>;
+
+    /// Parses a record from the value of each column in CSV output, in the same order as `COLUMNS`.
+    fn from_csv_fields(fields: &[String]) -> Result<Self>
+    where
+        Self: Sized;
+}
+
+/// Writes every listener sample taken since `since` for feeds matching `selector`.
+pub fn export_samples<W>(
+    db: &Database,
+    selector: Option<&FeedSelector>,
+    since: Option<&DateTime<Utc>>,
+    format: ExportFormat,
+    out: W,
+) -> Result<()>
+where
+    W: Write,
+{
+    let samples = ListenerSample::load_since(db, selector, since)
+        .context("failed to load listener samples")?;
+
+    write_records(&samples, format, out)
+}
+
+/// Writes the hourly listener averages of feeds matching `selector` that were seen since `since`.
+///
+/// Only feeds with an ID are known until they have a listener sample, so counties and locations are matched against
+/// the samples that have been recorded.
+pub fn export_averages<W>(
+    db: &Database,
+    selector: Option<&FeedSelector>,
+    since: Option<&DateTime<Utc>>,
+    format: ExportFormat,
+    out: W,
+) -> Result<()>
+where
+    W: Write,
+{
+    let ids = match selector {
+        None | Some(FeedSelector::Global) => None,
+        Some(FeedSelector::ID(id)) => Some(vec![*id as i32]),
+        Some(selector) => Some(
+            ListenerSample::feed_ids_matching(db, selector)
+                .context("failed to find feeds matching selector")?,
+        ),
+    };
+
+    let averages = ListenerAvg::load_since(db, ids.as_deref(), since)
+        .context("failed to load listener averages")?;
+
+    write_records(&averages, format, out)
+}
+
+pub fn write_records<T, W>(records: &[T], format: ExportFormat, mut out: W) -> Result<()>
+where
+    T: Record,
+    W: Write,
+{
+    match format {
+        ExportFormat::Csv => {
+            writeln!(out, "{}", T::COLUMNS.join(","))?;
+
+            for record in records {
+                let fields = record
+                    .csv_fields()
+                    .iter()
+                    .map(|field| escape_csv(field))
+                    .collect::<Vec<_>>();
+
+                writeln!(out, "{}", fields.join(","))?;
+            }
+        }
+        ExportFormat::Json => {
+            serde_json::to_writer(&mut out, records).context("failed to encode records")?;
+            writeln!(out)?;
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+/// Quotes a CSV field if it contains a character that would otherwise end it early.
+fn escape_csv(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
+
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(ToString::to_string).unwrap_or_default()
+}
+
+fn parse_field<T>(fields: &[String], index: usize, columns: &[&str]) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let column = columns[index];
+    let field = fields
+        .get(index)
+        .ok_or_else(|| anyhow!("missing value for {}", column))?;
+
+    field
+        .parse()
+        .with_context(|| format!("invalid value for {}: {}", column, field))
+}
+
+fn parse_optional<T>(fields: &[String], index: usize, columns: &[&str]) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match fields.get(index) {
+        Some(field) if field.is_empty() => Ok(None),
+        _ => parse_field(fields, index, columns).map(Some),
+    }
+}
+
+impl Record for ListenerSample {
+    const COLUMNS: &'static [&'static str] = &[
+        "time",
+        "feed_id",
+        "name",
+        "county",
+        "location",
+        "listeners",
+        "average",
+        "unskewed_average",
+        "jump",
+        "has_spiked",
+        "spike_count",
+        "alert",
+        "notified",
+    ];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.time.to_string(),
+            self.feed_id.to_string(),
+            self.name.clone(),
+            self.county.clone(),
+            self.location.clone(),
+            self.listeners.to_string(),
+            self.average.to_string(),
+            optional(&self.unskewed_average),
+            self.jump.to_string(),
+            self.has_spiked.to_string(),
+            self.spike_count.to_string(),
+            optional(&self.alert),
+            self.notified.to_string(),
+        ]
+    }
+
+    fn from_csv_fields(fields: &[String]) -> Result<Self> {
+        let cols = Self::COLUMNS;
+
+        Ok(Self {
+            // Samples are given a new ID when they're imported
+            id: 0,
+            time: parse_field(fields, 0, cols)?,
+            feed_id: parse_field(fields, 1, cols)?,
+            name: parse_field(fields, 2, cols)?,
+            county: parse_field(fields, 3, cols)?,
+            location: parse_field(fields, 4, cols)?,
+            listeners: parse_field(fields, 5, cols)?,
+            average: parse_field(fields, 6, cols)?,
+            unskewed_average: parse_optional(fields, 7, cols)?,
+            jump: parse_field(fields, 8, cols)?,
+            has_spiked: parse_field(fields, 9, cols)?,
+            spike_count: parse_field(fields, 10, cols)?,
+            alert: parse_optional(fields, 11, cols)?,
+            notified: parse_field(fields, 12, cols)?,
+        })
+    }
+}
+
+impl Record for ListenerAvg {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "last_seen",
+        "utc_0",
+        "utc_4",
+        "utc_8",
+        "utc_12",
+        "utc_16",
+        "utc_20",
+    ];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.last_seen.to_string(),
+            optional(&self.utc_0),
+            optional(&self.utc_4),
+            optional(&self.utc_8),
+            optional(&self.utc_12),
+            optional(&self.utc_16),
+            optional(&self.utc_20),
+        ]
+    }
+
+    fn from_csv_fields(fields: &[String]) -> Result<Self> {
+        let cols = Self::COLUMNS;
+
+        Ok(Self {
+            id: parse_field(fields, 0, cols)?,
+            last_seen: parse_field(fields, 1, cols)?,
+            utc_0: parse_optional(fields, 2, cols)?,
+            utc_4: parse_optional(fields, 3, cols)?,
+            utc_8: parse_optional(fields, 4, cols)?,
+            utc_12: parse_optional(fields, 5, cols)?,
+            utc_16: parse_optional(fields, 6, cols)?,
+            utc_20: parse_optional(fields, 7, cols)?,
+        })
+    }
+}
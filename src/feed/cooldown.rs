@@ -0,0 +1,129 @@
+use crate::config::Config;
+use crate::database::{self, feed_mutes, last_notifs, Database};
+use crate::feed::stats::ListenerStats;
+use crate::feed::Feed;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+
+/// Represents the last notification that was shown for a feed.
+#[derive(Queryable, Insertable, Debug)]
+pub struct LastNotif {
+    pub id: i32,
+    /// The time the notification was shown, as a UTC timestamp.
+    pub time: i64,
+    /// The listener jump the feed had when the notification was shown.
+    pub jump: f32,
+    /// The alert the feed had when the notification was shown.
+    pub alert: Option<String>,
+}
+
+impl LastNotif {
+    pub fn new(feed: &Feed, jump: f32, time: &DateTime<Utc>) -> Self {
+        Self {
+            id: feed.id as i32,
+            time: time.timestamp(),
+            jump,
+            alert: feed.alert.clone(),
+        }
+    }
+
+    pub fn load(db: &Database, feed_id: i32) -> diesel::QueryResult<Self> {
+        use crate::database::last_notifs::dsl::*;
+        last_notifs.filter(id.eq(feed_id)).get_result(db.conn())
+    }
+
+    pub fn save_to_db(&self, db: &Database) -> diesel::QueryResult<usize> {
+        use crate::database::last_notifs::dsl::*;
+
+        diesel::replace_into(last_notifs)
+            .values(self)
+            .execute(db.conn())
+    }
+
+    /// Removes notifications that were last shown more than `days` ago.
+    ///
+    /// Feeds that are only shown because of an alert will be shown again if their alert is still there afterwards.
+    pub fn remove_old_from_db(db: &Database, days: u32) -> diesel::QueryResult<usize> {
+        use crate::database::last_notifs::dsl::*;
+
+        let oldest_date = database::retention_cutoff(days);
+        diesel::delete(last_notifs.filter(time.lt(oldest_date))).execute(db.conn())
+    }
+
+    /// Returns true if a feed that was previously shown with this notification should be shown again.
+    ///
+    /// Feeds that are only being shown because of an alert will only be shown again when the alert changes.
+    /// Spiking feeds will be shown again once the cooldown has passed, or before then if the alert changes
+    /// or the listener jump has grown significantly.
+    pub fn allows_repeat(
+        &self,
+        feed: &Feed,
+        stats: &ListenerStats,
+        config: &Config,
+        cur_time: &DateTime<Utc>,
+    ) -> bool {
+        let alert_changed = feed.alert.is_some() && feed.alert != self.alert;
+
+        if !stats.has_spiked {
+            return alert_changed;
+        }
+
+        let cooldown_secs = (config.misc.notify_cooldown_mins * 60.0) as i64;
+
+        if cur_time.timestamp() - self.time >= cooldown_secs {
+            return true;
+        }
+
+        let escalation = self.jump * (1.0 + config.misc.renotify_jump_required.as_mult());
+        alert_changed || (self.jump > 0.0 && stats.jump >= escalation)
+    }
+}
+
+/// Represents a feed that should not be shown until a certain time.
+#[derive(Queryable, Insertable, Debug)]
+pub struct FeedMute {
+    pub id: i32,
+    /// The time the mute expires, as a UTC timestamp.
+    pub until: i64,
+}
+
+impl FeedMute {
+    pub fn new(feed_id: u32, hours: u32) -> Self {
+        Self {
+            id: feed_id as i32,
+            until: (Utc::now() + Duration::hours(hours as i64)).timestamp(),
+        }
+    }
+
+    pub fn save_to_db(&self, db: &Database) -> diesel::QueryResult<usize> {
+        use crate::database::feed_mutes::dsl::*;
+
+        diesel::replace_into(feed_mutes)
+            .values(self)
+            .execute(db.conn())
+    }
+
+    pub fn remove_from_db(db: &Database, feed_id: i32) -> diesel::QueryResult<usize> {
+        use crate::database::feed_mutes::dsl::*;
+        diesel::delete(feed_mutes.filter(id.eq(feed_id))).execute(db.conn())
+    }
+
+    pub fn is_muted(
+        db: &Database,
+        feed_id: i32,
+        cur_time: &DateTime<Utc>,
+    ) -> diesel::QueryResult<bool> {
+        use crate::database::feed_mutes::dsl::*;
+        use diesel::dsl::exists;
+
+        diesel::select(exists(
+            feed_mutes.filter(id.eq(feed_id).and(until.gt(cur_time.timestamp()))),
+        ))
+        .get_result(db.conn())
+    }
+
+    pub fn remove_old_from_db(db: &Database) -> diesel::QueryResult<usize> {
+        use crate::database::feed_mutes::dsl::*;
+        diesel::delete(feed_mutes.filter(until.lt(Utc::now().timestamp()))).execute(db.conn())
+    }
+}
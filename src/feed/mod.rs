@@ -1,22 +1,31 @@
+pub(crate) mod cooldown;
 pub mod stats;
 
-mod scrape;
+pub mod scrape;
 
 use crate::config::Config;
+use crate::metrics;
+use crate::template::Field;
 use anyhow::{anyhow, Context, Result};
-use notify_rust::Notification;
+use log::debug;
+use notify_rust::{Notification, Timeout, Urgency};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use serde::de::Visitor;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use stats::ListenerStats;
 use std::borrow::Cow;
 use std::cmp::{self, Eq, Ord};
+use std::collections::HashSet;
 use std::fmt;
 use std::result;
 use std::str::FromStr;
-use std::time::Duration;
-use strum_macros::EnumString;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use strum_macros::{EnumString, IntoStaticStr};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Feed<'a> {
     pub id: u32,
     pub name: String,
@@ -27,14 +36,58 @@ pub struct Feed<'a> {
 }
 
 impl<'a> Feed<'a> {
+    /// Scrapes feeds from every source specified in `config`.
+    ///
+    /// Each source is fetched on its own thread, and the scrape fails if any of them fail or they don't all finish before the update deadline.
+    /// Fetches that miss the deadline keep running in the background, and a source isn't fetched again until they finish.
     pub fn scrape_all(config: &Config) -> Result<Vec<Self>> {
-        let mut feeds = Self::scrape_source(Source::Top50, config.misc.minimum_listeners)?;
+        let misc = &config.misc;
+        let timeout = Duration::from_secs(misc.source_timeout_secs);
+        let deadline = Instant::now() + Duration::from_secs(misc.update_deadline_secs);
+
+        let mut sources = vec![Source::Top50];
+
+        if let Some(loc) = misc.location {
+            sources.push(Source::Location(loc));
+        }
+
+        let guards = sources
+            .iter()
+            .map(FetchGuard::acquire)
+            .collect::<Result<Vec<_>>>()?;
+
+        let num_sources = sources.len();
+        let (tx, rx) = mpsc::channel();
 
-        if let Some(loc) = config.misc.location {
-            let loc_feeds =
-                Self::scrape_source(Source::Location(loc), config.misc.minimum_listeners)?;
+        for (source, guard) in sources.into_iter().zip(guards) {
+            let tx = tx.clone();
+            let min_listeners = misc.minimum_listeners;
 
-            feeds.extend(loc_feeds);
+            thread::spawn(move || {
+                let start = Instant::now();
+                let result = Feed::scrape_source(&source, min_listeners, timeout);
+                drop(guard);
+
+                metrics::record_scrape(&source, start.elapsed(), result.is_ok());
+
+                // The receiver is gone if the deadline has already passed, so there's nothing to do with the result
+                tx.send(result).ok();
+            });
+        }
+
+        let mut feeds = Vec::new();
+
+        for _ in 0..num_sources {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            let result = rx.recv_timeout(remaining).map_err(|_| {
+                anyhow!(
+                    "feeds weren't scraped within {} seconds",
+                    misc.update_deadline_secs
+                )
+            })?;
+
+            feeds.extend(result?);
         }
 
         feeds.sort_unstable();
@@ -43,9 +96,17 @@ impl<'a> Feed<'a> {
         Ok(feeds)
     }
 
-    fn scrape_source(source: Source, min_listeners: u32) -> Result<Vec<Self>> {
+    /// Scrapes feeds with at least `min_listeners` from `source`, giving up if it takes longer than `timeout`.
+    pub fn scrape_source(
+        source: &Source,
+        min_listeners: u32,
+        timeout: Duration,
+    ) -> Result<Vec<Self>> {
+        debug!("fetching feeds from {}", source.url());
+
         let resp = attohttpc::get(source.url().as_ref())
-            .timeout(Duration::from_secs(15))
+            .connect_timeout(timeout)
+            .timeout(timeout)
             .send()
             .context("http request failed")?;
 
@@ -58,13 +119,20 @@ impl<'a> Feed<'a> {
 
         let body = resp.text().context("failed to read text from response")?;
 
-        match source {
+        let feeds = match *source {
             Source::Top50 => {
                 scrape::scrape_top(&body, min_listeners).context("failed to parse top 50 feeds")
             }
             Source::Location(location) => scrape::scrape_location(&body, min_listeners, location)
                 .with_context(|| anyhow!("failed to parse feeds for {}", location.abbrev())),
-        }
+        }?;
+
+        debug!("found {} feeds at {}", feeds.len(), source.url());
+        Ok(feeds)
+    }
+
+    pub fn url(&self) -> String {
+        format!("https://www.broadcastify.com/listen/feed/{}", self.id)
     }
 }
 
@@ -88,6 +156,45 @@ impl<'a> Ord for Feed<'a> {
     }
 }
 
+/// The names of the sources that are being fetched.
+static FETCHES_IN_FLIGHT: Lazy<Mutex<HashSet<&'static str>>> = Lazy::new(Default::default);
+
+/// Marks a source as being fetched until dropped.
+struct FetchGuard(&'static str);
+
+impl FetchGuard {
+    /// The most fetches that can run at once, including ones left running from previous updates.
+    const MAX_FETCHES: usize = 4;
+
+    fn acquire(source: &Source) -> Result<Self> {
+        let name = source.name();
+        let mut in_flight = FETCHES_IN_FLIGHT.lock();
+
+        if in_flight.contains(name) {
+            return Err(anyhow!(
+                "the previous fetch from {} is still running",
+                source.url()
+            ));
+        }
+
+        if in_flight.len() >= Self::MAX_FETCHES {
+            return Err(anyhow!(
+                "{} fetches from previous updates are still running",
+                in_flight.len()
+            ));
+        }
+
+        in_flight.insert(name);
+        Ok(Self(name))
+    }
+}
+
+impl Drop for FetchGuard {
+    fn drop(&mut self) {
+        FETCHES_IN_FLIGHT.lock().remove(self.0);
+    }
+}
+
 pub enum Source {
     Top50,
     Location(Location),
@@ -102,48 +209,170 @@ impl Source {
             }
         }
     }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Top50 => "top50",
+            Self::Location(loc) => loc.name(),
+        }
+    }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NotifKind {
+    /// The feed has spiked in listeners or has an alert.
+    Update,
+    /// The feed has reached a new multiple of its listener count before the incident started.
+    Escalating(f32),
+    /// The feed has fallen back to its listener count before the incident started.
+    Subsided,
+}
+
+/// Events that can occur after a notification has been shown.
 #[derive(Debug)]
+pub enum NotifEvent {
+    /// The user chose to mute the feed with the specified ID.
+    Mute(u32),
+}
+
+#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl Severity {
+    pub fn urgency(self) -> Urgency {
+        match self {
+            Self::Low => Urgency::Low,
+            Self::Normal => Urgency::Normal,
+            Self::Critical => Urgency::Critical,
+        }
+    }
+
+    pub fn timeout(self) -> Timeout {
+        match self {
+            Self::Low => Timeout::Milliseconds(5000),
+            Self::Normal => Timeout::Default,
+            Self::Critical => Timeout::Never,
+        }
+    }
+
+    pub fn icon(self) -> &'static str {
+        match self {
+            Self::Low => "dialog-information",
+            Self::Normal => "dialog-warning",
+            Self::Critical => "dialog-error",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct FeedNotif<'a> {
     pub feed: Feed<'a>,
     pub jump: f32,
+    /// The listener average the jump was calculated from.
+    pub baseline: f32,
+    pub average: f32,
+    pub unskewed_average: Option<f32>,
+    pub has_spiked: bool,
+    pub spike_count: u32,
+    pub kind: NotifKind,
 }
 
 impl<'a> FeedNotif<'a> {
-    pub fn new(feed: Feed<'a>, stats: &ListenerStats) -> Self {
+    pub fn new(feed: Feed<'a>, stats: &ListenerStats, kind: NotifKind) -> Self {
         Self {
+            baseline: feed.listeners as f32 - stats.jump,
             feed,
             jump: stats.jump,
+            average: stats.average.current,
+            unskewed_average: stats.unskewed_average,
+            has_spiked: stats.has_spiked,
+            spike_count: stats.spike_count,
+            kind,
+        }
+    }
+
+    pub fn severity(&self, config: &Config) -> Severity {
+        match self.kind {
+            NotifKind::Subsided => Severity::Low,
+            NotifKind::Escalating(_) => Severity::Critical,
+            NotifKind::Update if !self.has_spiked => Severity::Low,
+            NotifKind::Update => {
+                let critical_jump = config.notification.critical_jump.as_mult();
+
+                if self.feed.alert.is_some() || self.jump_pcnt() >= critical_jump {
+                    Severity::Critical
+                } else {
+                    Severity::Normal
+                }
+            }
+        }
+    }
+
+    /// Returns the listener jump as a multiplier of the feed's listener count before the jump.
+    pub fn jump_pcnt(&self) -> f32 {
+        if self.baseline <= 0.0 {
+            return 0.0;
+        }
+
+        self.jump / self.baseline
+    }
+
+    fn template_value(&self, field: Field, index: u32, max_index: u32) -> Cow<'_, str> {
+        match field {
+            Field::Index => index.to_string().into(),
+            Field::MaxIndex => max_index.to_string().into(),
+            Field::Status => match self.kind {
+                NotifKind::Update => "".into(),
+                NotifKind::Escalating(mult) => format!(" (escalated to {}x)", mult).into(),
+                NotifKind::Subsided => " (subsided)".into(),
+            },
+            Field::Id => self.feed.id.to_string().into(),
+            Field::Name => self.feed.name.as_str().into(),
+            Field::Url => self.feed.url().into(),
+            Field::County => self.feed.county.as_ref().into(),
+            Field::Location => self.feed.location.abbrev().into(),
+            Field::LocationName => self.feed.location.name().into(),
+            Field::Listeners => self.feed.listeners.to_string().into(),
+            Field::Jump => (self.jump as i32).to_string().into(),
+            Field::JumpPcnt => ((self.jump_pcnt() * 100.0) as i32).to_string().into(),
+            Field::Baseline => (self.baseline as i32).to_string().into(),
+            Field::Average => (self.average as i32).to_string().into(),
+            Field::UnskewedAverage => self
+                .unskewed_average
+                .map_or_else(|| "".into(), |avg| (avg as i32).to_string().into()),
+            Field::SpikeCount => self.spike_count.to_string().into(),
+            Field::Alert => self.feed.alert.as_deref().unwrap_or("").into(),
+            Field::AlertLine => match &self.feed.alert {
+                Some(alert) => format!("\nalert: {}", alert).into(),
+                None => "".into(),
+            },
         }
     }
 
-    pub fn show_notif(&self, index: u32, max_index: u32) -> Result<()> {
-        let title = format!(
-            concat!(env!("CARGO_PKG_NAME"), " update {} of {}"),
-            index, max_index
-        );
-
-        let alert = match &self.feed.alert {
-            Some(alert) => Cow::Owned(format!("\nalert: {}", alert)),
-            None => Cow::Borrowed(""),
-        };
-
-        let body = format!(
-            "{abbrev} | {name}\n{listeners} (^{jump}){alert}",
-            abbrev = self.feed.location.abbrev(),
-            name = self.feed.name,
-            listeners = self.feed.listeners,
-            jump = self.jump as i32,
-            alert = alert,
-        );
-
-        Notification::new()
+    pub(crate) fn build_notif(&self, index: u32, max_index: u32, config: &Config) -> Notification {
+        let notif_cfg = &config.notification;
+        let title = notif_cfg
+            .title
+            .render(|field| self.template_value(field, index, max_index));
+        let body = notif_cfg
+            .body
+            .render(|field| self.template_value(field, index, max_index));
+
+        let severity = self.severity(config);
+        let mut notif = Notification::new();
+
+        notif
             .summary(&title)
             .body(&body)
-            .show()
-            .map_err(|err| anyhow!("failed to create notification: {}", err))
-            .map(|_| ())
+            .urgency(severity.urgency())
+            .timeout(severity.timeout())
+            .icon(severity.icon());
+
+        notif
     }
 
     pub fn sort_all(notifs: &mut [Self], config: &Config) {
@@ -167,14 +396,12 @@ impl<'a> FeedNotif<'a> {
         });
     }
 
-    pub fn show_all(notifs: &[Self]) -> Result<()> {
-        let num_notifs = notifs.len() as u32;
-
-        for (i, notif) in notifs.iter().enumerate() {
-            notif.show_notif(1 + i as u32, num_notifs)?;
-        }
-
-        Ok(())
+    /// Returns a single line describing the feed, using the digest line template.
+    pub fn digest_line(&self, index: u32, max_index: u32, config: &Config) -> String {
+        config
+            .notification
+            .digest_line
+            .render(|field| self.template_value(field, index, max_index))
     }
 }
 
@@ -182,7 +409,7 @@ impl<'a> FeedNotif<'a> {
 /// Every location can be mapped to its state ID as it appears on Broadcastify.
 ///
 /// Airport feeds are used as a factor for being on this list because there's a chance of a major event occuring on them, at least more so than just amateur radio stations.
-#[derive(Copy, Clone, Debug, EnumString, Eq, FromPrimitive, Hash, PartialEq)]
+#[derive(Copy, Clone, Debug, EnumString, Eq, FromPrimitive, Hash, IntoStaticStr, PartialEq)]
 #[strum(serialize_all = "kebab_case")]
 #[repr(u32)]
 pub enum Location {
@@ -305,6 +532,12 @@ impl Location {
         self as u32
     }
 
+    /// Returns the name of the location as it's specified in the config.
+    #[inline(always)]
+    pub fn name(self) -> &'static str {
+        self.into()
+    }
+
     pub fn abbrev(self) -> &'static str {
         match self {
             // United States
@@ -440,3 +673,28 @@ impl<'de> Deserialize<'de> for Location {
         deserializer.deserialize_str(LocationVisitor)
     }
 }
+
+impl Serialize for Location {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fetch_guard_allows_one_fetch_per_source() {
+        let source = Source::Location(Location::UsAlaska);
+        let guard = FetchGuard::acquire(&source).unwrap();
+
+        assert!(FetchGuard::acquire(&source).is_err());
+
+        drop(guard);
+        assert!(FetchGuard::acquire(&source).is_ok());
+    }
+}
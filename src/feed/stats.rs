@@ -1,62 +1,155 @@
 use crate::config::Config;
-use crate::database::listener_avgs;
-use crate::database::Database;
-use crate::feed::Feed;
-use chrono::{Duration, Utc, Weekday};
+use crate::database::{self, feed_stats, listener_avgs, Database};
+use crate::feed::{Feed, NotifKind};
+use chrono::{DateTime, Duration, Timelike, Utc, Weekday};
 use diesel::prelude::*;
-use std::collections::HashMap;
+use log::{debug, trace};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 
-/// Represents an average set of data that wraps around its specified sample size.
+/// Represents an average of the most recent samples, where older samples have less weight than newer ones.
+///
+/// Samples are timestamped so that gaps between them (such as from the system being suspended) are accounted for.
 #[derive(Debug, Clone)]
 pub struct Average {
-    /// The current average. It is updated after calling to self.add_sample().
+    /// The current average. It is updated when self.add_sample() adds a sample.
     pub current: f32,
-    /// The current average before the last call to self.add_sample().
+    /// The current average before the last sample was added.
     pub last: f32,
-    /// The raw data that is used to calculate the current and last average.
-    pub data: [i32; Self::SAMPLE_SIZE],
-    /// The current data index.
-    index: usize,
-    /// This keeps track of how many samples have been added since the struct
-    /// was created, up until it reaches the specified sample size.
-    /// It prevents data values that haven't been added from being averaged.
-    populated: usize,
+    /// The raw data that is used to calculate the current and last average, ordered from oldest to newest.
+    samples: VecDeque<Sample>,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+pub struct Sample {
+    pub value: i32,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub time: DateTime<Utc>,
 }
 
 impl Average {
     pub const SAMPLE_SIZE: usize = 5;
+    /// The number of update times it takes for a sample to have half of the weight of a new one.
+    const HALF_LIFE_STEPS: i32 = Self::SAMPLE_SIZE as i32;
+    /// The number of update times it takes for a sample to be too old to use.
+    const MAX_AGE_STEPS: i32 = Self::SAMPLE_SIZE as i32 * 2;
 
     pub fn new() -> Self {
-        Self::with_sample(0.0)
+        Self {
+            current: 0.0,
+            last: 0.0,
+            samples: VecDeque::with_capacity(Self::SAMPLE_SIZE),
+        }
     }
 
-    pub fn with_sample(value: f32) -> Self {
+    /// Creates an average with a single sample taken at `time`.
+    ///
+    /// The sample is weighted like any other, so new samples are blended with it instead of replacing it.
+    pub fn with_sample(value: i32, time: DateTime<Utc>) -> Self {
+        let mut samples = VecDeque::with_capacity(Self::SAMPLE_SIZE);
+        samples.push_back(Sample { value, time });
+
         Self {
-            current: value,
+            current: value as f32,
             last: 0.0,
-            data: [0; Self::SAMPLE_SIZE],
-            index: 0,
-            populated: 0,
+            samples,
         }
     }
 
-    /// Adds a new sample to the data and calculates the new average.
-    pub fn add_sample(&mut self, value: i32) {
-        if self.index >= self.data.len() {
-            self.index = 0;
+    /// Adds a sample taken at `time` if one is due, and calculates the new average. Returns true if the sample was added.
+    ///
+    /// `step` should be the configured update time. A sample is due once every step, which keeps the average covering
+    /// the same amount of time when updates run faster than the configured update time. The step also determines how
+    /// quickly samples lose weight.
+    pub fn add_sample(&mut self, value: i32, time: DateTime<Utc>, step: Duration) -> bool {
+        let time = match self.next_sample_time(time, step) {
+            Some(time) => time,
+            None => return false,
+        };
+
+        if self.samples.len() >= Self::SAMPLE_SIZE {
+            self.samples.pop_front();
         }
 
-        self.data[self.index] = value;
-        self.index += 1;
+        self.samples.push_back(Sample { value, time });
+
+        self.last = self.current;
+        self.current = self.weighted_average(step);
+
+        true
+    }
 
-        if self.populated < self.index {
-            self.populated += 1;
+    /// Returns true if a sample taken at `time` would be added to the average.
+    pub fn is_sample_due(&self, time: DateTime<Utc>, step: Duration) -> bool {
+        self.next_sample_time(time, step).is_some()
+    }
+
+    /// Returns the time to record a sample taken at `time` with, or None if it's too soon after the newest sample.
+    fn next_sample_time(&self, time: DateTime<Utc>, step: Duration) -> Option<DateTime<Utc>> {
+        let newest = match self.samples.back() {
+            Some(newest) => newest.time,
+            None => return Some(time),
+        };
+
+        // Rounding lets updates that run slightly early due to jitter still take a sample
+        let elapsed = (time - newest).num_seconds() as f32 / step.num_seconds().max(1) as f32;
+
+        match elapsed.round() as i64 {
+            i64::MIN..=0 => None,
+            // Stepping from the newest sample keeps samples from drifting later and later
+            1 => Some(newest + step),
+            // Updates were slower than usual or missed, so there's nothing to stay in step with
+            _ => Some(time),
         }
+    }
 
-        self.last = self.current;
+    /// Removes samples that are too old to be meaningful as of `now`, and returns the number that were removed.
+    ///
+    /// The current average is left as-is if every sample is removed.
+    pub fn expire(&mut self, now: DateTime<Utc>, step: Duration) -> usize {
+        let max_age = step * Self::MAX_AGE_STEPS;
+        let len = self.samples.len();
 
-        self.current =
-            self.data.iter().take(self.populated).sum::<i32>() as f32 / self.populated as f32;
+        self.samples.retain(|sample| now - sample.time <= max_age);
+
+        let num_removed = len - self.samples.len();
+
+        if num_removed > 0 && !self.samples.is_empty() {
+            self.last = self.current;
+            self.current = self.weighted_average(step);
+        }
+
+        num_removed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn samples(&self) -> impl Iterator<Item = &Sample> {
+        self.samples.iter()
+    }
+
+    /// Weights each sample by half for every half life that passed between it and the newest sample.
+    fn weighted_average(&self, step: Duration) -> f32 {
+        let newest = match self.samples.back() {
+            Some(newest) => newest.time,
+            None => return 0.0,
+        };
+
+        let half_life = (step * Self::HALF_LIFE_STEPS).num_seconds().max(1) as f32;
+        let mut total = 0.0;
+        let mut total_weight = 0.0;
+
+        for sample in &self.samples {
+            let age = (newest - sample.time).num_seconds().max(0) as f32;
+            let weight = 0.5f32.powf(age / half_life);
+
+            total += sample.value as f32 * weight;
+            total_weight += weight;
+        }
+
+        total / total_weight
     }
 }
 
@@ -66,7 +159,7 @@ impl Default for Average {
     }
 }
 
-#[derive(Queryable, Insertable, Debug)]
+#[derive(Queryable, Insertable, Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct ListenerAvg {
     pub id: i32,
     pub last_seen: i64,
@@ -97,6 +190,27 @@ impl ListenerAvg {
         listener_avgs.filter(id.eq(feed_id)).get_result(db.conn())
     }
 
+    /// Loads the averages of feeds seen since the specified time, optionally limited to the feeds in `ids`.
+    pub fn load_since(
+        db: &Database,
+        ids: Option<&[i32]>,
+        since: Option<&DateTime<Utc>>,
+    ) -> diesel::QueryResult<Vec<Self>> {
+        use crate::database::listener_avgs::dsl::*;
+
+        let mut query = listener_avgs.into_boxed();
+
+        if let Some(ids) = ids {
+            query = query.filter(id.eq_any(ids.to_vec()));
+        }
+
+        if let Some(since) = since {
+            query = query.filter(last_seen.ge(since.timestamp()));
+        }
+
+        query.order(id.asc()).load(db.conn())
+    }
+
     pub fn load_or_new(db: &Database, feed_id: i32) -> Self {
         Self::load(db, feed_id).unwrap_or_else(|_| Self::new(feed_id))
     }
@@ -109,12 +223,33 @@ impl ListenerAvg {
             .execute(db.conn())
     }
 
-    pub fn remove_old_from_db(db: &Database) -> diesel::QueryResult<usize> {
-        use crate::database::listener_avgs::dsl::*;
+    /// Combines two sets of averages for the same feed.
+    ///
+    /// The averages of whichever one was seen most recently are used, with any hours they're missing taken from the other.
+    pub fn merge(self, other: Self) -> Self {
+        let (newer, older) = if self.last_seen >= other.last_seen {
+            (self, other)
+        } else {
+            (other, self)
+        };
+
+        Self {
+            id: newer.id,
+            last_seen: newer.last_seen,
+            utc_0: newer.utc_0.or(older.utc_0),
+            utc_4: newer.utc_4.or(older.utc_4),
+            utc_8: newer.utc_8.or(older.utc_8),
+            utc_12: newer.utc_12.or(older.utc_12),
+            utc_16: newer.utc_16.or(older.utc_16),
+            utc_20: newer.utc_20.or(older.utc_20),
+        }
+    }
 
-        let today = Utc::now();
-        let oldest_date = (today - Duration::days(30)).timestamp();
+    /// Removes the averages of feeds that haven't been seen for `days`.
+    pub fn remove_old_from_db(db: &Database, days: u32) -> diesel::QueryResult<usize> {
+        use crate::database::listener_avgs::dsl::*;
 
+        let oldest_date = database::retention_cutoff(days);
         diesel::delete(listener_avgs.filter(last_seen.lt(oldest_date))).execute(db.conn())
     }
 
@@ -169,6 +304,12 @@ pub struct ListenerStats {
     pub has_spiked: bool,
     /// Represents the number of times the feed has spiked consecutively.
     pub spike_count: u32,
+    /// The highest multiple of the unskewed average the feed has reached during the current incident.
+    pub escalation: Option<f32>,
+    /// Indicates whether or not the feed reached a new escalation multiple since the last update.
+    pub has_escalated: bool,
+    /// Indicates whether or not an escalated feed has fallen back to its unskewed average since the last update.
+    pub has_subsided: bool,
 }
 
 impl ListenerStats {
@@ -181,39 +322,151 @@ impl ListenerStats {
     const UNSKEWED_ADJUST_PCNT: f32 = 0.0075;
     const UNSKEWED_SPIKES_REQUIRED: u32 = 1;
 
-    pub fn init_from_db(db: &Database, hour: u8, feed_id: i32, cur_listeners: f32) -> Self {
+    /// Loads the statistics saved by the last update the feed was in, so incidents carry over between runs.
+    ///
+    /// Feeds without saved statistics start with an average of their hourly average, or `cur_listeners` if they
+    /// don't have one for the current hour.
+    pub fn init_from_db(
+        db: &Database,
+        cur_time: &DateTime<Utc>,
+        feed_id: i32,
+        cur_listeners: f32,
+        config: &Config,
+    ) -> Self {
         let listener_avg = ListenerAvg::load_or_new(db, feed_id);
 
+        match SavedStats::load(db, feed_id).optional() {
+            Ok(Some(saved)) => match saved.into_stats(listener_avg.clone()) {
+                Ok(stats) => return stats,
+                Err(err) => debug!(
+                    "ignoring unreadable statistics of feed {}: {}",
+                    feed_id, err
+                ),
+            },
+            Ok(None) => (),
+            Err(err) => debug!("failed to load statistics of feed {}: {}", feed_id, err),
+        }
+
         let listeners = listener_avg
-            .for_hour(hour)
+            .for_hour(cur_time.hour() as u8)
             .map_or(cur_listeners, |l| l as f32);
 
+        // Seeding the average as if it was sampled in the last update lets the first sample blend in with it
+        Self::new(
+            listener_avg,
+            listeners,
+            *cur_time - Self::update_step(config),
+        )
+    }
+
+    fn new(listener_avg: ListenerAvg, listeners: f32, time: DateTime<Utc>) -> Self {
         Self {
             listener_avg,
-            average: Average::with_sample(listeners as f32),
+            average: Average::with_sample(listeners as i32, time),
             unskewed_average: None,
             jump: 0.0,
             has_spiked: false,
             spike_count: 0,
+            escalation: None,
+            has_escalated: false,
+            has_subsided: false,
         }
     }
 
     /// Updates the listener data and determines if the feed has spiked
-    pub fn update(&mut self, hour: u8, feed: &Feed, config: &Config, weekday: Weekday) {
+    pub fn update(
+        &mut self,
+        cur_time: &DateTime<Utc>,
+        feed: &Feed,
+        config: &Config,
+        weekday: Weekday,
+    ) {
+        let step = Self::update_step(config);
+
+        // Samples from before a long gap (such as the system being suspended) would otherwise cause phantom spikes
+        if self.average.expire(*cur_time, step) > 0 && self.average.is_empty() {
+            debug!(
+                "feed {} hasn't been sampled in a while, resetting its statistics",
+                feed.id
+            );
+
+            let listeners = self
+                .listener_avg
+                .for_hour(cur_time.hour() as u8)
+                .map_or(feed.listeners as i32, |l| l);
+
+            self.average = Average::with_sample(listeners, *cur_time - step);
+
+            // Whatever incident the feed was in can't be followed across the gap
+            self.unskewed_average = None;
+            self.spike_count = 0;
+            self.escalation = None;
+        }
+
+        let sample_due = self.average.is_sample_due(*cur_time, step);
+
         self.jump = feed.listeners as f32 - self.current_listener_average();
         self.has_spiked = self.is_spiking(feed, config, weekday);
 
-        self.spike_count = if self.has_spiked {
+        // Spikes are counted per sample, so polling faster doesn't make them add up quicker
+        self.spike_count = if !self.has_spiked {
+            0
+        } else if sample_due || self.spike_count == 0 {
             self.spike_count + 1
         } else {
-            0
+            self.spike_count
         };
 
-        self.average.add_sample(feed.listeners as i32);
-        self.update_unskewed_average(feed.listeners as f32);
+        let prev_unskewed = self.unskewed_average;
+
+        // The unskewed average adjusts by a fixed amount per sample, so it only changes along with the average
+        if self
+            .average
+            .add_sample(feed.listeners as i32, *cur_time, step)
+        {
+            self.update_unskewed_average(feed.listeners as f32);
+        }
+
+        // Escalation only compares the current listeners against a threshold, so it's checked on every update
+        // to catch incidents as soon as possible while updates are running faster
+        self.update_escalation(feed.listeners as f32, config);
+
+        match (prev_unskewed, self.unskewed_average) {
+            (None, Some(unskewed)) => debug!(
+                "feed {} has spiked consistently, setting unskewed average to {:.1}",
+                feed.id, unskewed
+            ),
+            (Some(_), None) => debug!(
+                "feed {} is back near its unskewed average, removing it",
+                feed.id
+            ),
+            _ => (),
+        }
 
-        self.listener_avg
-            .set_hour(hour, self.current_listener_average() as i32);
+        if self.has_escalated {
+            debug!("feed {} escalated to {:?}x", feed.id, self.escalation);
+        }
+
+        self.listener_avg.set_hour(
+            cur_time.hour() as u8,
+            self.current_listener_average() as i32,
+        );
+
+        trace!(
+            "feed {} ({}): {} listeners, {:.1} avg, {:?} unskewed avg, ^{:.1} jump, {} spikes",
+            feed.id,
+            feed.name,
+            feed.listeners,
+            self.average.current,
+            self.unskewed_average,
+            self.jump,
+            self.spike_count
+        );
+    }
+
+    /// Returns the configured update time, which is the rate the average is sampled at.
+    fn update_step(config: &Config) -> Duration {
+        Duration::seconds((config.misc.update_time_mins * 60.0).max(1.0) as i64)
     }
 
     /// Returns true if the specified feed is currently spiking in listeners
@@ -273,6 +526,38 @@ impl ListenerStats {
         }
     }
 
+    fn update_escalation(&mut self, listeners: f32, config: &Config) {
+        self.has_escalated = false;
+        self.has_subsided = false;
+
+        let unskewed = match self.unskewed_average {
+            Some(unskewed) if unskewed > 0.0 => unskewed,
+            _ => {
+                // The unskewed average is removed once the feed is close to it again, so any
+                // escalation from the last incident is over
+                self.has_subsided = self.escalation.take().is_some();
+                return;
+            }
+        };
+
+        let mult = listeners / unskewed;
+
+        let reached = config
+            .escalation
+            .multiples
+            .iter()
+            .copied()
+            .filter(|&required| mult >= required)
+            .fold(None, |highest: Option<f32>, required| {
+                Some(highest.map_or(required, |highest| highest.max(required)))
+            });
+
+        if reached > self.escalation {
+            self.escalation = reached;
+            self.has_escalated = true;
+        }
+    }
+
     /// Returns a listener average that is resiliant to large sudden jumps.
     ///
     /// This is useful for preserving the integrity of the listener average over time.
@@ -283,21 +568,331 @@ impl ListenerStats {
     pub fn should_display_feed(&self, feed: &Feed, config: &Config) -> bool {
         if let Some(max_times) = config.misc.show_max_times {
             if self.spike_count > max_times {
+                debug!(
+                    "feed {} has spiked {} times in a row, which is more than show_max_times",
+                    feed.id, self.spike_count
+                );
+
                 return false;
             }
         }
 
         let has_alert = feed.alert.is_some() && config.misc.show_alert_feeds;
+
+        if self.has_spiked {
+            debug!("feed {} spiked by {:.1} listeners", feed.id, self.jump);
+        } else if has_alert {
+            debug!("feed {} has an alert", feed.id);
+        }
+
         self.has_spiked || has_alert
     }
 
+    /// Returns the kind of notification that should be shown for the feed, if any.
+    ///
+    /// Escalation and subsided notifications are not limited by the show_max_times option.
+    pub fn notif_kind(&self, feed: &Feed, config: &Config) -> Option<NotifKind> {
+        if self.has_subsided && config.escalation.notify_subsided {
+            return Some(NotifKind::Subsided);
+        }
+
+        match self.escalation {
+            Some(mult) if self.has_escalated => Some(NotifKind::Escalating(mult)),
+            _ if self.should_display_feed(feed, config) => Some(NotifKind::Update),
+            _ => None,
+        }
+    }
+
     pub fn save_to_db(&self, db: &Database) -> diesel::QueryResult<usize> {
-        self.listener_avg.save_to_db(db)
+        use crate::database::feed_stats::dsl::*;
+
+        self.listener_avg.save_to_db(db)?;
+
+        diesel::replace_into(feed_stats)
+            .values(SavedStats::new(self)?)
+            .execute(db.conn())
+    }
+
+    /// Removes the saved statistics of every feed, so they start over from their hourly average.
+    pub fn remove_all_from_db(db: &Database) -> diesel::QueryResult<usize> {
+        diesel::delete(feed_stats::table).execute(db.conn())
+    }
+
+    /// Removes the saved statistics of feeds that haven't been seen for `days`.
+    pub fn remove_old_from_db(db: &Database, days: u32) -> diesel::QueryResult<usize> {
+        use crate::database::feed_stats::dsl::*;
+
+        let oldest_date = database::retention_cutoff(days);
+        diesel::delete(feed_stats.filter(last_seen.lt(oldest_date))).execute(db.conn())
+    }
+}
+
+/// The part of a feed's statistics that carries over between updates, as it's saved in the database.
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "feed_stats"]
+struct SavedStats {
+    id: i32,
+    last_seen: i64,
+    /// The samples of the average, as a JSON array.
+    samples: String,
+    average: f32,
+    last_average: f32,
+    unskewed_average: Option<f32>,
+    spike_count: i32,
+    escalation: Option<f32>,
+}
+
+impl SavedStats {
+    fn new(stats: &ListenerStats) -> diesel::QueryResult<Self> {
+        use diesel::result::Error;
+
+        let samples = stats.average.samples().collect::<Vec<_>>();
+        let samples =
+            serde_json::to_string(&samples).map_err(|err| Error::SerializationError(err.into()))?;
+
+        Ok(Self {
+            id: stats.listener_avg.id,
+            last_seen: stats.listener_avg.last_seen,
+            samples,
+            average: stats.average.current,
+            last_average: stats.average.last,
+            unskewed_average: stats.unskewed_average,
+            spike_count: stats.spike_count as i32,
+            escalation: stats.escalation,
+        })
+    }
+
+    fn load(db: &Database, feed_id: i32) -> diesel::QueryResult<Self> {
+        use crate::database::feed_stats::dsl::*;
+        feed_stats.filter(id.eq(feed_id)).get_result(db.conn())
+    }
+
+    fn into_stats(self, listener_avg: ListenerAvg) -> serde_json::Result<ListenerStats> {
+        let samples = serde_json::from_str::<VecDeque<Sample>>(&self.samples)?;
+
+        Ok(ListenerStats {
+            listener_avg,
+            average: Average {
+                current: self.average,
+                last: self.last_average,
+                samples,
+            },
+            unskewed_average: self.unskewed_average,
+            jump: 0.0,
+            has_spiked: false,
+            spike_count: self.spike_count as u32,
+            escalation: self.escalation,
+            has_escalated: false,
+            has_subsided: false,
+        })
     }
 }
 
 pub type ListenerStatMap = HashMap<u32, ListenerStats>;
 
+/// The state of a feed as of the update it was scraped in.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedSample {
+    /// The UTC timestamp of the update.
+    pub time: i64,
+    pub id: u32,
+    pub name: String,
+    pub location: &'static str,
+    pub county: String,
+    pub listeners: u32,
+    pub average: f32,
+    pub unskewed_average: Option<f32>,
+    pub jump: f32,
+    pub has_spiked: bool,
+    pub spike_count: u32,
+    pub alert: Option<String>,
+    /// Indicates whether or not the feed was shown in a notification.
+    ///
+    /// This is only set once [`Updater::notify`](crate::Updater::notify) has shown it.
+    pub notified: bool,
+}
+
+impl FeedSample {
+    pub fn new(feed: &Feed, stats: &ListenerStats, time: &DateTime<Utc>) -> Self {
+        Self {
+            time: time.timestamp(),
+            id: feed.id,
+            name: feed.name.clone(),
+            location: feed.location.abbrev(),
+            county: feed.county.to_string(),
+            listeners: feed.listeners,
+            average: stats.average.current,
+            unskewed_average: stats.unskewed_average,
+            jump: stats.jump,
+            has_spiked: stats.has_spiked,
+            spike_count: stats.spike_count,
+            alert: feed.alert.clone(),
+            notified: false,
+        }
+    }
+}
+
 fn lerp(v0: f32, v1: f32, t: f32) -> f32 {
     (1. - t) * v0 + t * v1
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{feed, start, step};
+
+    #[test]
+    fn samples_are_added_once_per_step() {
+        let mut avg = Average::new();
+
+        assert!(avg.add_sample(10, start(), step()));
+        assert!(!avg.add_sample(20, start() + Duration::minutes(2), step()));
+        assert!(!avg.add_sample(
+            20,
+            start() + Duration::minutes(2) + Duration::seconds(50),
+            step()
+        ));
+        // Updates that run a little early because of jitter still count
+        assert!(avg.add_sample(30, start() + Duration::minutes(5), step()));
+        assert!(!avg.is_sample_due(start() + Duration::minutes(8), step()));
+        assert!(avg.is_sample_due(start() + Duration::minutes(10), step()));
+
+        let times = avg.samples().map(|sample| sample.time).collect::<Vec<_>>();
+        assert_eq!(times, vec![start(), start() + step()]);
+    }
+
+    #[test]
+    fn unskewed_average_adjusts_per_sample() {
+        let config = Config::default();
+
+        let run = |poll_mins: i64| {
+            let mut stats = ListenerStats::new(ListenerAvg::new(1), 150.0, start() - step());
+            stats.unskewed_average = Some(100.0);
+
+            let mut time = start();
+
+            while time <= start() + Duration::hours(2) {
+                stats.update(&time, &feed(1, 150), &config, Weekday::Mon);
+                time = time + Duration::minutes(poll_mins);
+            }
+
+            stats.unskewed_average.unwrap()
+        };
+
+        let normal = run(6);
+
+        assert!(normal > 100.0);
+        assert!((run(2) - normal).abs() < 0.01);
+        assert!((run(1) - normal).abs() < 0.01);
+    }
+
+    #[test]
+    fn samples_lose_half_their_weight_every_half_life() {
+        let mut avg = Average::new();
+        let half_life = step() * Average::HALF_LIFE_STEPS;
+
+        avg.add_sample(100, start(), step());
+        avg.add_sample(200, start() + half_life, step());
+
+        // The older sample has half the weight of the newer one
+        let expected = (100.0 * 0.5 + 200.0) / 1.5;
+        assert!((avg.current - expected).abs() < 0.01);
+        assert_eq!(avg.last, 100.0);
+    }
+
+    #[test]
+    fn samples_of_the_same_age_are_weighted_equally() {
+        let mut avg = Average::new();
+
+        for i in 0..Average::SAMPLE_SIZE as i32 {
+            avg.add_sample(50, start() + step() * i, step());
+        }
+
+        assert!((avg.current - 50.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn oldest_samples_are_dropped_past_sample_size() {
+        let mut avg = Average::new();
+
+        for i in 0..Average::SAMPLE_SIZE as i32 + 2 {
+            avg.add_sample(i, start() + step() * i, step());
+        }
+
+        assert_eq!(avg.samples().count(), Average::SAMPLE_SIZE);
+        assert_eq!(avg.samples().next().unwrap().value, 2);
+    }
+
+    #[test]
+    fn samples_expire_past_max_age() {
+        let mut avg = Average::new();
+
+        avg.add_sample(100, start(), step());
+        avg.add_sample(200, start() + step(), step());
+
+        let max_age = step() * Average::MAX_AGE_STEPS;
+
+        assert_eq!(avg.expire(start() + max_age, step()), 0);
+        assert_eq!(
+            avg.expire(start() + max_age + Duration::seconds(1), step()),
+            1
+        );
+        assert_eq!(avg.current, 200.0);
+
+        assert_eq!(avg.expire(start() + step() + max_age * 2, step()), 1);
+        assert!(avg.is_empty());
+        // Nothing is left to recalculate the average from, so the last one is kept
+        assert_eq!(avg.current, 200.0);
+    }
+
+    #[test]
+    fn average_resets_to_hourly_average_after_samples_expire() {
+        let config = Config::default();
+        let mut listener_avg = ListenerAvg::new(1);
+        listener_avg.set_hour(start().hour() as u8, 40);
+
+        let mut stats = ListenerStats::new(listener_avg, 40.0, start() - step());
+
+        for i in 0..Average::SAMPLE_SIZE as i32 {
+            stats.update(
+                &(start() + step() * i),
+                &feed(1, 500),
+                &config,
+                Weekday::Mon,
+            );
+        }
+
+        assert!(stats.average.current > 400.0);
+
+        // The hourly average follows the feed while it's sampled, so put it back to what it was before
+        stats.listener_avg.set_hour(start().hour() as u8, 40);
+        stats.unskewed_average = Some(40.0);
+        stats.escalation = Some(2.0);
+
+        let resumed = start() + step() * (Average::SAMPLE_SIZE as i32 + Average::MAX_AGE_STEPS);
+        stats.update(&resumed, &feed(1, 40), &config, Weekday::Mon);
+
+        // The hourly average is seeded as a sample from the last update, which the new one is blended with
+        assert_eq!(stats.average.samples().count(), 2);
+        assert!((stats.average.last - 40.0).abs() < 0.01);
+        assert!((stats.average.current - 40.0).abs() < 0.01);
+        assert!(!stats.has_spiked);
+
+        // The incident from before the gap is forgotten rather than shown as subsided
+        assert_eq!(stats.unskewed_average, None);
+        assert_eq!(stats.escalation, None);
+        assert_eq!(stats.spike_count, 0);
+        assert!(!stats.has_subsided);
+    }
+
+    #[test]
+    fn first_sample_blends_with_the_hourly_average() {
+        let config = Config::default();
+        let mut stats = ListenerStats::new(ListenerAvg::new(1), 100.0, start() - step());
+
+        stats.update(&start(), &feed(1, 200), &config, Weekday::Mon);
+
+        assert_eq!(stats.average.samples().count(), 2);
+        assert!(stats.average.current > 100.0 && stats.average.current < 200.0);
+    }
+}
@@ -0,0 +1,25 @@
+//! Values shared by the unit tests of different modules.
+
+use crate::feed::{Feed, Location};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+/// The time of the first update in a test.
+pub fn start() -> DateTime<Utc> {
+    Utc.ymd(2021, 3, 1).and_hms(12, 0, 0)
+}
+
+/// The time between updates in a test.
+pub fn step() -> Duration {
+    Duration::minutes(6)
+}
+
+pub fn feed(id: u32, listeners: u32) -> Feed<'static> {
+    Feed {
+        id,
+        name: format!("Feed {}", id),
+        listeners,
+        location: Location::UsAlaska,
+        county: "Anchorage".into(),
+        alert: None,
+    }
+}
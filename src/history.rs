@@ -0,0 +1,398 @@
+use crate::config::FeedSelector;
+use crate::database::{self, listener_samples, notif_history, Database};
+use crate::feed::stats::FeedSample;
+use crate::feed::{FeedNotif, NotifKind};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, TimeZone, Utc};
+use diesel::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+
+/// Represents a notification that was shown in the past.
+#[derive(Queryable, Serialize, Debug)]
+pub struct HistoryEntry {
+    pub id: i32,
+    /// The time the notification was shown, as a UTC timestamp.
+    pub time: i64,
+    /// The kind of notification that was shown. This is either "update", "escalating", "subsided", or "error".
+    pub kind: String,
+    pub message: Option<String>,
+    pub feed_id: Option<i32>,
+    pub name: Option<String>,
+    pub county: Option<String>,
+    pub location: Option<String>,
+    pub listeners: Option<i32>,
+    pub jump: Option<f32>,
+    pub baseline: Option<f32>,
+    pub average: Option<f32>,
+    pub unskewed_average: Option<f32>,
+    pub spike_count: Option<i32>,
+    pub alert: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "notif_history"]
+struct NewHistoryEntry<'a> {
+    time: i64,
+    kind: &'a str,
+    message: Option<Cow<'a, str>>,
+    feed_id: Option<i32>,
+    name: Option<&'a str>,
+    county: Option<&'a str>,
+    location: Option<&'a str>,
+    listeners: Option<i32>,
+    jump: Option<f32>,
+    baseline: Option<f32>,
+    average: Option<f32>,
+    unskewed_average: Option<f32>,
+    spike_count: Option<i32>,
+    alert: Option<&'a str>,
+}
+
+impl<'a> NewHistoryEntry<'a> {
+    fn save_to_db(&self, db: &Database) -> diesel::QueryResult<usize> {
+        use crate::database::notif_history::dsl::*;
+
+        diesel::insert_into(notif_history)
+            .values(self)
+            .execute(db.conn())
+    }
+}
+
+impl HistoryEntry {
+    pub fn record_notif(
+        db: &Database,
+        notif: &FeedNotif,
+        time: &DateTime<Utc>,
+    ) -> diesel::QueryResult<usize> {
+        let (kind, message) = match notif.kind {
+            NotifKind::Update => ("update", None),
+            NotifKind::Escalating(mult) => {
+                ("escalating", Some(format!("escalated to {}x", mult).into()))
+            }
+            NotifKind::Subsided => ("subsided", None),
+        };
+
+        let feed = &notif.feed;
+
+        NewHistoryEntry {
+            time: time.timestamp(),
+            kind,
+            message,
+            feed_id: Some(feed.id as i32),
+            name: Some(&feed.name),
+            county: Some(&feed.county),
+            location: Some(feed.location.abbrev()),
+            listeners: Some(feed.listeners as i32),
+            jump: Some(notif.jump),
+            baseline: Some(notif.baseline),
+            average: Some(notif.average),
+            unskewed_average: notif.unskewed_average,
+            spike_count: Some(notif.spike_count as i32),
+            alert: feed.alert.as_deref(),
+        }
+        .save_to_db(db)
+    }
+
+    pub fn record_error(
+        db: &Database,
+        err: &anyhow::Error,
+        time: &DateTime<Utc>,
+    ) -> diesel::QueryResult<usize> {
+        NewHistoryEntry {
+            time: time.timestamp(),
+            kind: "error",
+            message: Some(format!("{:?}", err).into()),
+            feed_id: None,
+            name: None,
+            county: None,
+            location: None,
+            listeners: None,
+            jump: None,
+            baseline: None,
+            average: None,
+            unskewed_average: None,
+            spike_count: None,
+            alert: None,
+        }
+        .save_to_db(db)
+    }
+
+    pub fn load_since(db: &Database, since: &DateTime<Utc>) -> diesel::QueryResult<Vec<Self>> {
+        use crate::database::notif_history::dsl::*;
+
+        notif_history
+            .filter(time.ge(since.timestamp()))
+            .order(time.asc())
+            .load(db.conn())
+    }
+
+    /// Removes entries that were recorded more than `days` ago.
+    pub fn remove_old_from_db(db: &Database, days: u32) -> diesel::QueryResult<usize> {
+        use crate::database::notif_history::dsl::*;
+
+        let oldest_date = database::retention_cutoff(days);
+        diesel::delete(notif_history.filter(time.lt(oldest_date))).execute(db.conn())
+    }
+
+    /// Returns the name of every county a notification has been shown for.
+    pub fn known_counties(db: &Database) -> diesel::QueryResult<Vec<String>> {
+        use crate::database::notif_history::dsl::*;
+
+        let counties = notif_history
+            .select(county)
+            .filter(county.is_not_null())
+            .distinct()
+            .load::<Option<String>>(db.conn())?;
+
+        Ok(counties.into_iter().flatten().collect())
+    }
+
+    pub fn print_recent(db: &Database, since: &DateTime<Utc>, json: bool) -> Result<()> {
+        let entries = Self::load_since(db, since).context("failed to load notification history")?;
+
+        for entry in entries {
+            if json {
+                let line =
+                    serde_json::to_string(&entry).context("failed to encode history entry")?;
+                println!("{}", line);
+            } else {
+                println!("{}", entry);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Represents the state of a feed during a past update.
+#[derive(Queryable, Deserialize, Serialize, Debug)]
+pub struct ListenerSample {
+    pub id: i32,
+    /// The time of the update, as a UTC timestamp.
+    pub time: i64,
+    pub feed_id: i32,
+    pub name: String,
+    pub county: String,
+    pub location: String,
+    pub listeners: i32,
+    pub average: f32,
+    pub unskewed_average: Option<f32>,
+    pub jump: f32,
+    pub has_spiked: bool,
+    pub spike_count: i32,
+    pub alert: Option<String>,
+    /// Indicates whether or not the feed was shown in a notification.
+    ///
+    /// Feeds held back by quiet hours aren't counted, even if they're shown in a summary later.
+    pub notified: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "listener_samples"]
+struct NewListenerSample<'a> {
+    time: i64,
+    feed_id: i32,
+    name: &'a str,
+    county: &'a str,
+    location: &'a str,
+    listeners: i32,
+    average: f32,
+    unskewed_average: Option<f32>,
+    jump: f32,
+    has_spiked: bool,
+    spike_count: i32,
+    alert: Option<&'a str>,
+    notified: bool,
+}
+
+impl<'a> From<&'a FeedSample> for NewListenerSample<'a> {
+    fn from(sample: &'a FeedSample) -> Self {
+        Self {
+            time: sample.time,
+            feed_id: sample.id as i32,
+            name: &sample.name,
+            county: &sample.county,
+            location: sample.location,
+            listeners: sample.listeners as i32,
+            average: sample.average,
+            unskewed_average: sample.unskewed_average,
+            jump: sample.jump,
+            has_spiked: sample.has_spiked,
+            spike_count: sample.spike_count as i32,
+            alert: sample.alert.as_deref(),
+            notified: sample.notified,
+        }
+    }
+}
+
+impl ListenerSample {
+    /// Saves a sample from another database, unless one already exists for the same feed and time.
+    ///
+    /// Returns true if the sample was saved.
+    pub fn import(&self, db: &Database) -> diesel::QueryResult<bool> {
+        use crate::database::listener_samples::dsl::*;
+
+        let num_existing = listener_samples
+            .filter(feed_id.eq(self.feed_id))
+            .filter(time.eq(self.time))
+            .count()
+            .get_result::<i64>(db.conn())?;
+
+        if num_existing > 0 {
+            return Ok(false);
+        }
+
+        diesel::insert_into(listener_samples)
+            .values(NewListenerSample {
+                time: self.time,
+                feed_id: self.feed_id,
+                name: &self.name,
+                county: &self.county,
+                location: &self.location,
+                listeners: self.listeners,
+                average: self.average,
+                unskewed_average: self.unskewed_average,
+                jump: self.jump,
+                has_spiked: self.has_spiked,
+                spike_count: self.spike_count,
+                alert: self.alert.as_deref(),
+                notified: self.notified,
+            })
+            .execute(db.conn())?;
+
+        Ok(true)
+    }
+
+    pub fn record_all(db: &Database, samples: &[FeedSample]) -> diesel::QueryResult<()> {
+        use crate::database::listener_samples::dsl::*;
+
+        for sample in samples {
+            diesel::insert_into(listener_samples)
+                .values(NewListenerSample::from(sample))
+                .execute(db.conn())?;
+        }
+
+        Ok(())
+    }
+
+    /// Marks the sample of a feed taken at `sample_time` as shown in a notification.
+    pub fn mark_notified(
+        db: &Database,
+        sample_feed_id: i32,
+        sample_time: i64,
+    ) -> diesel::QueryResult<()> {
+        use crate::database::listener_samples::dsl::*;
+
+        diesel::update(
+            listener_samples
+                .filter(feed_id.eq(sample_feed_id))
+                .filter(time.eq(sample_time)),
+        )
+        .set(notified.eq(true))
+        .execute(db.conn())?;
+
+        Ok(())
+    }
+
+    /// Loads every sample taken since the specified time that matches `selector`, in order of time.
+    pub fn load_since(
+        db: &Database,
+        selector: Option<&FeedSelector>,
+        since: Option<&DateTime<Utc>>,
+    ) -> diesel::QueryResult<Vec<Self>> {
+        use crate::database::listener_samples::dsl::*;
+
+        let mut query = listener_samples.into_boxed();
+
+        if let Some(since) = since {
+            query = query.filter(time.ge(since.timestamp()));
+        }
+
+        // Counties and locations are easier to compare here than in SQL
+        if let Some(FeedSelector::ID(sel_id)) = selector {
+            query = query.filter(feed_id.eq(*sel_id as i32));
+        }
+
+        let mut samples = query.order(time.asc()).load::<Self>(db.conn())?;
+
+        if let Some(selector) = selector {
+            samples.retain(|sample| sample.matches(selector));
+        }
+
+        Ok(samples)
+    }
+
+    /// Returns the ID of every feed that has a sample matching `selector`.
+    pub fn feed_ids_matching(
+        db: &Database,
+        selector: &FeedSelector,
+    ) -> diesel::QueryResult<Vec<i32>> {
+        use crate::database::listener_samples::dsl::*;
+
+        let feeds = listener_samples
+            .select((feed_id, county, location))
+            .distinct()
+            .load::<(i32, String, String)>(db.conn())?;
+
+        let mut ids = feeds
+            .into_iter()
+            .filter(|(sample_id, county_name, location_abbrev)| {
+                sample_matches(selector, *sample_id, county_name, location_abbrev)
+            })
+            .map(|(sample_id, _, _)| sample_id)
+            .collect::<Vec<_>>();
+
+        ids.sort_unstable();
+        ids.dedup();
+
+        Ok(ids)
+    }
+
+    /// Removes samples that were taken more than `days` ago.
+    pub fn remove_old_from_db(db: &Database, days: u32) -> diesel::QueryResult<usize> {
+        use crate::database::listener_samples::dsl::*;
+
+        let oldest_date = database::retention_cutoff(days);
+        diesel::delete(listener_samples.filter(time.lt(oldest_date))).execute(db.conn())
+    }
+
+    pub fn matches(&self, selector: &FeedSelector) -> bool {
+        sample_matches(selector, self.feed_id, &self.county, &self.location)
+    }
+}
+
+fn sample_matches(selector: &FeedSelector, id: i32, county: &str, location: &str) -> bool {
+    match selector {
+        FeedSelector::Global => true,
+        FeedSelector::ID(sel_id) => *sel_id as i32 == id,
+        FeedSelector::County(name) => name.eq_ignore_ascii_case(county),
+        FeedSelector::Location(loc) => loc.abbrev() == location,
+    }
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = Local.timestamp(self.time, 0).format("%Y-%m-%d %H:%M");
+        write!(f, "{} {:<10}", time, self.kind)?;
+
+        if let (Some(location), Some(name)) = (&self.location, &self.name) {
+            write!(f, " {} | {}", location, name)?;
+        }
+
+        if let (Some(listeners), Some(jump)) = (self.listeners, self.jump) {
+            write!(f, ": {} (^{})", listeners, jump as i32)?;
+        }
+
+        if let Some(message) = &self.message {
+            write!(f, " [{}]", message)?;
+        }
+
+        if let Some(alert) = &self.alert {
+            write!(f, " alert: {}", alert)?;
+        }
+
+        Ok(())
+    }
+}
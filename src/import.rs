@@ -0,0 +1,300 @@
+use crate::database::Database;
+use crate::export::Record;
+use crate::feed::stats::ListenerAvg;
+use crate::history::ListenerSample;
+use anyhow::{anyhow, Context, Result};
+use diesel::prelude::*;
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+/// The number of records read from an imported file, and how many of them changed the database.
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub averages_read: usize,
+    pub averages_merged: usize,
+    pub samples_read: usize,
+    pub samples_added: usize,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.samples_read > 0 {
+            write!(
+                f,
+                "added {} of {} listener samples",
+                self.samples_added, self.samples_read
+            )
+        } else {
+            write!(
+                f,
+                "merged {} of {} listener averages",
+                self.averages_merged, self.averages_read
+            )
+        }
+    }
+}
+
+/// Imports listener averages or samples written by the export command of another instance.
+///
+/// Either CSV or JSON can be imported, and the kind of data is detected from the columns in the file. Averages are merged
+/// with the ones already saved by preferring whichever were seen most recently, while samples are only added if one
+/// doesn't already exist for the same feed and time. Nothing is saved if any record fails to import.
+pub fn import(db: &Database, contents: &str) -> Result<ImportSummary> {
+    let data = Data::parse(contents)?;
+    let mut summary = ImportSummary::default();
+
+    db.conn()
+        .transaction::<_, anyhow::Error, _>(|| {
+            match data {
+                Data::Averages(averages) => {
+                    summary.averages_read = averages.len();
+
+                    for avg in averages {
+                        if merge_average(db, avg)? {
+                            summary.averages_merged += 1;
+                        }
+                    }
+                }
+                Data::Samples(samples) => {
+                    summary.samples_read = samples.len();
+
+                    for sample in samples {
+                        if sample.import(db)? {
+                            summary.samples_added += 1;
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        })
+        .context("database transaction failed")?;
+
+    Ok(summary)
+}
+
+/// Merges `avg` into the averages saved for the same feed. Returns true if the saved averages changed.
+fn merge_average(db: &Database, avg: ListenerAvg) -> QueryResult<bool> {
+    let existing = ListenerAvg::load(db, avg.id).optional()?;
+
+    let merged = match existing {
+        Some(existing) => {
+            let merged = existing.clone().merge(avg);
+
+            if merged == existing {
+                return Ok(false);
+            }
+
+            merged
+        }
+        None => avg,
+    };
+
+    merged.save_to_db(db)?;
+    Ok(true)
+}
+
+enum Data {
+    Averages(Vec<ListenerAvg>),
+    Samples(Vec<ListenerSample>),
+}
+
+impl Data {
+    fn parse(contents: &str) -> Result<Self> {
+        if contents.trim_start().starts_with('[') {
+            Self::parse_json(contents)
+        } else {
+            Self::parse_csv(contents)
+        }
+    }
+
+    fn parse_json(contents: &str) -> Result<Self> {
+        let values: Vec<serde_json::Value> =
+            serde_json::from_str(contents).context("invalid JSON")?;
+
+        let first = match values.first() {
+            Some(first) => first,
+            None => return Ok(Self::Averages(Vec::new())),
+        };
+
+        if first.get("feed_id").is_some() {
+            from_json_values(values).map(Self::Samples)
+        } else if first.get("last_seen").is_some() {
+            from_json_values(values).map(Self::Averages)
+        } else {
+            Err(anyhow!("unrecognized records in JSON"))
+        }
+    }
+
+    fn parse_csv(contents: &str) -> Result<Self> {
+        let mut rows = parse_csv_rows(contents)?.into_iter();
+
+        let header = match rows.next() {
+            Some(header) => header,
+            None => return Ok(Self::Averages(Vec::new())),
+        };
+
+        if header == ListenerSample::COLUMNS {
+            from_csv_rows(rows).map(Self::Samples)
+        } else if header == ListenerAvg::COLUMNS {
+            from_csv_rows(rows).map(Self::Averages)
+        } else {
+            Err(anyhow!("unrecognized CSV columns: {}", header.join(",")))
+        }
+    }
+}
+
+fn from_json_values<T>(values: Vec<serde_json::Value>) -> Result<Vec<T>>
+where
+    T: DeserializeOwned,
+{
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| {
+            serde_json::from_value(value).with_context(|| format!("invalid record {}", i + 1))
+        })
+        .collect()
+}
+
+fn from_csv_rows<T, I>(rows: I) -> Result<Vec<T>>
+where
+    T: Record,
+    I: Iterator<Item = Vec<String>>,
+{
+    rows.enumerate()
+        .map(|(i, row)| {
+            if row.len() != T::COLUMNS.len() {
+                return Err(anyhow!(
+                    "expected {} fields on line {} but found {}",
+                    T::COLUMNS.len(),
+                    i + 2,
+                    row.len()
+                ));
+            }
+
+            T::from_csv_fields(&row).with_context(|| format!("invalid record on line {}", i + 2))
+        })
+        .collect()
+}
+
+/// Splits CSV into rows of fields, undoing the quoting done by the export command.
+///
+/// Line numbers in errors assume that no field spans multiple lines, which only alerts are likely to do.
+fn parse_csv_rows(contents: &str) -> Result<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = contents.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            }
+            '"' if field.is_empty() => in_quotes = true,
+            _ if in_quotes => field.push(c),
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(anyhow!("unterminated quoted field in CSV"));
+    }
+
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.iter().map(|&field| field.into()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn parse_plain_rows() {
+        let parsed = parse_csv_rows("id,name\n1,Feed\n2,Other\n").unwrap();
+        assert_eq!(
+            parsed,
+            rows(&[&["id", "name"], &["1", "Feed"], &["2", "Other"]])
+        );
+    }
+
+    #[test]
+    fn parse_quoted_fields() {
+        let parsed = parse_csv_rows("1,\"Fire, EMS\",\"say \"\"hi\"\"\",\"two\nlines\"\n").unwrap();
+        assert_eq!(
+            parsed,
+            rows(&[&["1", "Fire, EMS", "say \"hi\"", "two\nlines"]])
+        );
+    }
+
+    #[test]
+    fn parse_empty_fields() {
+        let parsed = parse_csv_rows("1,,2,\n,,\n").unwrap();
+        assert_eq!(parsed, rows(&[&["1", "", "2", ""], &["", "", ""]]));
+    }
+
+    #[test]
+    fn parse_crlf_line_endings() {
+        let parsed = parse_csv_rows("id,name\r\n1,Feed\r\n2,\"a\r\nb\"\r\n").unwrap();
+        assert_eq!(
+            parsed,
+            rows(&[&["id", "name"], &["1", "Feed"], &["2", "a\r\nb"]])
+        );
+    }
+
+    #[test]
+    fn parse_missing_trailing_newline() {
+        let parsed = parse_csv_rows("id,name\n1,Feed").unwrap();
+        assert_eq!(parsed, rows(&[&["id", "name"], &["1", "Feed"]]));
+    }
+
+    #[test]
+    fn parse_unterminated_quote() {
+        assert!(parse_csv_rows("1,\"Feed\n").is_err());
+    }
+
+    #[test]
+    fn parse_averages_with_empty_hours() {
+        let csv = "id,last_seen,utc_0,utc_4,utc_8,utc_12,utc_16,utc_20\r\n5,100,10,,,40,,\r\n";
+
+        let averages = match Data::parse(csv).unwrap() {
+            Data::Averages(averages) => averages,
+            Data::Samples(_) => panic!("averages were parsed as samples"),
+        };
+
+        assert_eq!(averages.len(), 1);
+        assert_eq!(averages[0].id, 5);
+        assert_eq!(averages[0].utc_0, Some(10));
+        assert_eq!(averages[0].utc_4, None);
+        assert_eq!(averages[0].utc_12, Some(40));
+        assert_eq!(averages[0].utc_20, None);
+    }
+
+    #[test]
+    fn reject_unknown_columns() {
+        assert!(Data::parse("id,something\n1,2\n").is_err());
+    }
+}
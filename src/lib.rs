@@ -0,0 +1,68 @@
+//! Tools for finding Broadcastify feeds that are spiking in listeners.
+//!
+//! This is the library behind the `bcnotif` daemon. Feeds are scraped and tracked across updates by an [`Updater`],
+//! which picks the ones to show and passes them to a [`Notifier`]. Single sources can also be scraped with
+//! [`Feed::scrape_source`] (or the lower-level functions in [`feed::scrape`]).
+//!
+//! ```no_run
+//! use bcnotif::{Config, Database, DesktopNotifier, Updater};
+//! use chrono::Utc;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let config = Config::load_or_new()?;
+//! let mut updater = Updater::new(Database::open()?);
+//! let mut notifier = DesktopNotifier::without_actions(config.notification.mode);
+//!
+//! let cur_time = Utc::now();
+//! let mut update = updater.update(&config, &cur_time)?;
+//! updater.notify(&mut update, &config, &mut notifier, &cur_time)?;
+//!
+//! for feed in &update.feeds {
+//!     println!("{} ({}): {} listeners", feed.name, feed.location, feed.listeners);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate num_derive;
+
+pub mod config;
+pub mod feed;
+pub mod notifier;
+pub mod template;
+pub mod update;
+
+pub(crate) mod database;
+pub(crate) mod err;
+pub(crate) mod export;
+#[cfg(test)]
+mod fixtures;
+pub(crate) mod history;
+pub(crate) mod import;
+pub(crate) mod metrics;
+pub(crate) mod path;
+
+pub use crate::config::Config;
+pub use crate::database::Database;
+pub use crate::feed::stats::{Average, FeedSample, ListenerStats};
+pub use crate::feed::{Feed, FeedNotif, Location, NotifEvent, NotifKind, Source};
+pub use crate::notifier::{DesktopNotifier, Notifier};
+pub use crate::template::Template;
+pub use crate::update::{Update, Updater};
+
+// Plumbing for the `bcnotif` binary. These aren't part of the library's API and may change at any time.
+#[doc(hidden)]
+pub use crate::err::error_notif;
+#[doc(hidden)]
+pub use crate::export::{export_averages, export_samples, ExportFormat};
+#[doc(hidden)]
+pub use crate::history::HistoryEntry;
+#[doc(hidden)]
+pub use crate::import::{import, ImportSummary};
+#[doc(hidden)]
+pub use crate::metrics::serve as serve_metrics;
+#[doc(hidden)]
+pub use crate::path::FilePath;
@@ -0,0 +1,239 @@
+use anyhow::{anyhow, Context, Result};
+use bcnotif::config::{LogLevel, LogOptions, LogOutput};
+use bcnotif::FilePath;
+use chrono::Local;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+static LOGGER: Lazy<Logger> = Lazy::new(|| Logger {
+    sink: Mutex::new(Sink::Stderr),
+    level_override: Mutex::new(None),
+});
+
+struct Logger {
+    sink: Mutex<Sink>,
+    /// The level specified on the command line, which takes priority over the config.
+    level_override: Mutex<Option<LogLevel>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Messages from our dependencies are too noisy to be useful below the warning level
+        let max_level = if metadata.target().starts_with(env!("CARGO_PKG_NAME")) {
+            log::max_level()
+        } else {
+            log::max_level().min(LevelFilter::Warn)
+        };
+
+        metadata.level() <= max_level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // There's nowhere left to report a failure to log to
+        self.sink.lock().write(record).ok();
+    }
+
+    fn flush(&self) {
+        if let Sink::File(file) = &mut *self.sink.lock() {
+            file.file.flush().ok();
+        }
+    }
+}
+
+/// Sets up logging for the daemon.
+///
+/// `level_override` is used instead of the level in `options`, even after the config is reloaded.
+pub fn init(options: &LogOptions, level_override: Option<LogLevel>) -> Result<()> {
+    log::set_logger(&*LOGGER).map_err(|err| anyhow!("failed to set logger: {}", err))?;
+    *LOGGER.level_override.lock() = level_override;
+    apply(options)
+}
+
+/// Switches logging to the output and level specified in `options`.
+pub fn apply(options: &LogOptions) -> Result<()> {
+    let sink = Sink::open(options)?;
+    *LOGGER.sink.lock() = sink;
+
+    let level = LOGGER.level_override.lock().unwrap_or(options.level);
+    log::set_max_level(level.into());
+
+    Ok(())
+}
+
+enum Sink {
+    Stderr,
+    Journal(UnixDatagram),
+    File(RotatingFile),
+}
+
+impl Sink {
+    const JOURNAL_SOCKET: &'static str = "/run/systemd/journal/socket";
+
+    fn open(options: &LogOptions) -> Result<Self> {
+        match options.output {
+            LogOutput::Stderr => Ok(Self::Stderr),
+            LogOutput::Journal => {
+                let socket = UnixDatagram::unbound().context("failed to create journal socket")?;
+
+                socket
+                    .connect(Self::JOURNAL_SOCKET)
+                    .context("failed to connect to the journal")?;
+
+                Ok(Self::Journal(socket))
+            }
+            LogOutput::File => {
+                let path = match &options.file {
+                    Some(path) => path.clone(),
+                    None => {
+                        let mut path = FilePath::LocalData.validated_dir_path()?;
+                        path.push(concat!(env!("CARGO_PKG_NAME"), ".log"));
+                        path
+                    }
+                };
+
+                let file =
+                    RotatingFile::open(path, options.max_file_size_kb * 1024, options.max_files)?;
+                Ok(Self::File(file))
+            }
+        }
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        match self {
+            Self::Stderr => writeln!(io::stderr(), "{}", format_line(record)),
+            Self::Journal(socket) => {
+                socket.send(&journal_entry(record))?;
+                Ok(())
+            }
+            Self::File(file) => file.write_line(&format_line(record)),
+        }
+    }
+}
+
+fn format_line(record: &Record) -> String {
+    format!(
+        "{} {:<5} {}",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        record.level(),
+        record.args()
+    )
+}
+
+/// Encodes a record with the journal's native protocol.
+///
+/// Values that contain a new line have to be written with their length in front of them instead of being terminated by one.
+fn journal_entry(record: &Record) -> Vec<u8> {
+    let priority = match record.level() {
+        Level::Error => "3",
+        Level::Warn => "4",
+        Level::Info => "6",
+        Level::Debug | Level::Trace => "7",
+    };
+
+    let message = record.args().to_string();
+    let mut entry = Vec::with_capacity(message.len() + 64);
+
+    let mut push_field = |name: &str, value: &str| {
+        entry.extend_from_slice(name.as_bytes());
+
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    };
+
+    push_field("PRIORITY", priority);
+    push_field("SYSLOG_IDENTIFIER", env!("CARGO_PKG_NAME"));
+    push_field("MESSAGE", &message);
+
+    entry
+}
+
+/// A log file that is moved aside once it reaches a certain size.
+///
+/// Old logs have a number appended to them, with 1 being the most recent.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: u32) -> Result<Self> {
+        let file = Self::open_file(&path)?;
+        let size = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn open_file(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| anyhow!("failed to open log file at {}", path.display()))
+    }
+
+    fn rotated_path(&self, num: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", num));
+        path.into()
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files > 0 {
+            for num in (1..self.max_files).rev() {
+                let from = self.rotated_path(num);
+
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(num + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, self.rotated_path(1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        self.file =
+            Self::open_file(&self.path).map_err(|err| io::Error::other(format!("{:#}", err)))?;
+
+        self.size = 0;
+        Ok(())
+    }
+}
@@ -1,246 +1,823 @@
-#[macro_use]
-extern crate diesel;
-#[macro_use]
-extern crate num_derive;
-
-mod config;
-mod database;
-mod err;
-mod feed;
-mod path;
-
-use crate::feed::stats::{ListenerAvg, ListenerStatMap, ListenerStats};
-use crate::feed::{Feed, FeedNotif};
+mod ctl;
+mod logger;
+mod scheduler;
+
 use anyhow::{anyhow, Context, Result};
-use chrono::{DateTime, Datelike, Duration, Local, Timelike, Utc};
-use config::Config;
-use database::Database;
-use diesel::prelude::*;
-use once_cell::sync::Lazy;
-use parking_lot::{Condvar, Mutex};
-use smallvec::SmallVec;
+use bcnotif::config::FeedSelector;
+use bcnotif::config::{check, init, Config, LogLevel};
+use bcnotif::{
+    Database, DesktopNotifier, ExportFormat, FeedSample, HistoryEntry, NotifEvent, Updater,
+};
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone, Utc};
+use ctl::{FeedState, Request, Response};
+use log::{debug, error, info, warn};
+use parking_lot::Mutex;
+use scheduler::Scheduler;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
 use std::sync::{mpsc, Arc};
 use std::thread;
+use strum_macros::EnumString;
 
 struct CmdOptions {
     reload_config: bool,
+    once: bool,
+    output: Option<OutputFormat>,
+    log_level: Option<LogLevel>,
+    command: Option<Command>,
 }
 
 impl CmdOptions {
-    fn from_env() -> Self {
+    fn from_env() -> Result<Self> {
         let mut args = pico_args::Arguments::from_env();
 
         if args.contains(["-h", "--help"]) {
             Self::print_help();
         }
 
-        Self {
-            reload_config: args.contains(["-r", "--reload"]),
-        }
+        let reload_config = args.contains(["-r", "--reload"]);
+        let once = args.contains("--once");
+        let output = args.opt_value_from_fn("--output", |value| {
+            OutputFormat::from_str(value).map_err(|_| anyhow!("unknown output format: {}", value))
+        })?;
+        let log_level = args.opt_value_from_fn("--log-level", |value| {
+            LogLevel::from_str(value).map_err(|_| anyhow!("unknown log level: {}", value))
+        })?;
+
+        let command = match args.subcommand()?.as_deref() {
+            Some("recent") => Some(Command::Recent {
+                since: args
+                    .opt_value_from_fn("--since", |value| parse_duration(value).and_then(time_ago))?
+                    .unwrap_or_else(|| Utc::now() - Duration::days(1)),
+                json: args.contains("--json"),
+            }),
+            Some("export") => {
+                let averages = match args.subcommand()?.as_deref() {
+                    Some("samples") => false,
+                    Some("averages") => true,
+                    Some(data) => return Err(anyhow!("unknown data to export: {}", data)),
+                    None => return Err(anyhow!("missing data to export")),
+                };
+
+                Some(Command::Export {
+                    averages,
+                    feed: args.opt_value_from_fn("--feed", parse_feed_selector)?,
+                    since: args.opt_value_from_fn("--since", parse_since)?,
+                    format: args
+                        .opt_value_from_fn("--format", |value| {
+                            ExportFormat::from_str(value)
+                                .map_err(|_| anyhow!("unknown export format: {}", value))
+                        })?
+                        .unwrap_or(ExportFormat::Csv),
+                })
+            }
+            Some("import") => Some(Command::Import {
+                path: args
+                    .free_from_str()?
+                    .ok_or_else(|| anyhow!("missing file to import"))?,
+            }),
+            Some("ctl") => Some(Command::Ctl(Request::from_args(&args.free()?)?)),
+            Some("config") => match args.subcommand()?.as_deref() {
+                Some("check") => Some(Command::ConfigCheck),
+                Some("init") => Some(Command::ConfigInit {
+                    force: args.contains("--force"),
+                }),
+                Some(cmd) => return Err(anyhow!("unknown config command: {}", cmd)),
+                None => return Err(anyhow!("missing config command")),
+            },
+            Some(cmd) => return Err(anyhow!("unknown command: {}", cmd)),
+            None => None,
+        };
+
+        Ok(Self {
+            reload_config,
+            once,
+            output,
+            log_level,
+            command,
+        })
     }
 
     fn print_help() {
-        println!(concat!("Usage: ", env!("CARGO_PKG_NAME"), " [OPTIONS]\n"));
+        println!(concat!(
+            "Usage: ",
+            env!("CARGO_PKG_NAME"),
+            " [OPTIONS] [COMMAND]\n"
+        ));
 
         println!("Optional arguments:");
         println!("  -h, --help    show this message");
-        println!("  -r, --reload  reload the configuration file on each update");
+        println!("  -r, --reload  reload the configuration file when it changes");
+        println!("  --once        run a single update, print the feeds that were shown, and exit");
+        println!("                with 0 if any were shown, 2 if there was nothing to show, or 1 on errors");
+        println!("  --output <jsonl>");
+        println!(
+            "                print the state of every scraped feed to stdout after each update"
+        );
+        println!("  --log-level <off | error | warn | info | debug | trace>");
+        println!("                the minimum level of messages to log, overriding the configuration file");
+
+        println!("\nCommands:");
+        println!("  recent [--since <duration>] [--json]");
+        println!("      show notifications from the last day, or the specified duration (such as 2h or 30m)");
+        println!("  export <samples | averages> [--feed <id | selector>] [--since <date | duration>] [--format <csv | json>]");
+        println!("      export the listeners of every feed from each update, or the hourly listener averages of every feed");
+        println!("  import <file>");
+        println!("      merge listener averages or samples exported by another instance into the database");
+        println!("  ctl <update | reload | mute <id> [hours] | unmute <id> | stats | shutdown>");
+        println!("      control a running instance");
+        println!("  config check");
+        println!("      check the configuration file for errors and likely mistakes");
+        println!("  config init [--force]");
+        println!("      write a configuration file with every option set to its default value");
 
         std::process::exit(0);
     }
 }
 
+/// The format to print the state of scraped feeds in after each update.
+#[derive(Copy, Clone, Debug, EnumString)]
+#[strum(serialize_all = "lowercase")]
+enum OutputFormat {
+    /// A JSON object on each line for every feed.
+    Jsonl,
+}
+
+impl OutputFormat {
+    fn print(self, samples: &[FeedSample]) -> Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        match self {
+            Self::Jsonl => {
+                for sample in samples {
+                    serde_json::to_writer(&mut stdout, sample)?;
+                    stdout.write_all(b"\n")?;
+                }
+            }
+        }
+
+        // Whatever is reading our output shouldn't have to wait for the buffer to fill up
+        stdout.flush().context("failed to write feeds to stdout")
+    }
+}
+
+enum Command {
+    Recent {
+        since: DateTime<Utc>,
+        json: bool,
+    },
+    Export {
+        averages: bool,
+        feed: Option<FeedSelector>,
+        since: Option<DateTime<Utc>>,
+        format: ExportFormat,
+    },
+    Import {
+        path: PathBuf,
+    },
+    Ctl(Request),
+    ConfigCheck,
+    ConfigInit {
+        force: bool,
+    },
+}
+
+impl Command {
+    fn run(self) -> Result<()> {
+        match self {
+            Self::Recent { since, json } => {
+                let db = Database::open().context("failed to open feed database")?;
+                HistoryEntry::print_recent(&db, &since, json)
+            }
+            Self::Export {
+                averages,
+                feed,
+                since,
+                format,
+            } => {
+                let db = Database::open().context("failed to open feed database")?;
+                let stdout = io::stdout();
+
+                if averages {
+                    bcnotif::export_averages(
+                        &db,
+                        feed.as_ref(),
+                        since.as_ref(),
+                        format,
+                        stdout.lock(),
+                    )
+                } else {
+                    bcnotif::export_samples(
+                        &db,
+                        feed.as_ref(),
+                        since.as_ref(),
+                        format,
+                        stdout.lock(),
+                    )
+                }
+            }
+            Self::Import { path } => {
+                // The daemon saves the averages it has in memory on every update, which would undo the import
+                if ctl::is_daemon_running() {
+                    return Err(anyhow!(
+                        "another instance is running and would overwrite the imported data, stop it with `{} ctl shutdown` first",
+                        env!("CARGO_PKG_NAME")
+                    ));
+                }
+
+                let contents = fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+
+                let db = Database::open().context("failed to open feed database")?;
+                let summary = bcnotif::import(&db, &contents)
+                    .with_context(|| format!("failed to import {}", path.display()))?;
+
+                println!("{}", summary);
+                Ok(())
+            }
+            Self::Ctl(request) => match ctl::send(&request)? {
+                Response::Ok => Ok(()),
+                Response::Stats { mut feeds } => {
+                    feeds.sort_unstable_by_key(|feed| feed.id);
+
+                    for feed in feeds {
+                        println!(
+                            "{}: {} avg, {} unskewed avg, ^{} jump, {} spikes",
+                            feed.id,
+                            feed.average as i32,
+                            feed.unskewed_average.map_or(-1, |avg| avg as i32),
+                            feed.jump as i32,
+                            feed.spike_count,
+                        );
+                    }
+
+                    Ok(())
+                }
+                Response::Error { message } => Err(anyhow!(message)),
+            },
+            Self::ConfigCheck => check_config(),
+            Self::ConfigInit { force } => init_config(force),
+        }
+    }
+}
+
+fn check_config() -> Result<()> {
+    let path = Config::validated_path()?;
+
+    let contents = fs::read_to_string(&path)
+        .with_context(|| anyhow!("failed to load config at {}", path.display()))?;
+
+    // Counties are only used for warnings, so there's no need to fail if they can't be loaded
+    let known_counties = Database::open()
+        .ok()
+        .and_then(|db| HistoryEntry::known_counties(&db).ok())
+        .unwrap_or_default();
+
+    let diagnostics = check::check(&contents, &known_counties);
+
+    for diag in &diagnostics {
+        println!("{}", diag);
+    }
+
+    let num_errors = diagnostics
+        .iter()
+        .filter(|diag| diag.level == check::Level::Error)
+        .count();
+
+    let num_warnings = diagnostics.len() - num_errors;
+
+    println!(
+        "{}: {} error(s), {} warning(s)",
+        path.display(),
+        num_errors,
+        num_warnings
+    );
+
+    if num_errors > 0 {
+        return Err(anyhow!("config check failed"));
+    }
+
+    Ok(())
+}
+
+fn init_config(force: bool) -> Result<()> {
+    let path = Config::validated_path()?;
+
+    if path.exists() && !force {
+        return Err(anyhow!(
+            "config already exists at {}, use --force to overwrite it",
+            path.display()
+        ));
+    }
+
+    let contents = init::default_config()?;
+
+    fs::write(&path, contents)
+        .with_context(|| anyhow!("failed to write config to {}", path.display()))?;
+
+    println!("wrote default config to {}", path.display());
+    Ok(())
+}
+
+/// Parses a feed ID or a feed selector like the ones used in the config.
+fn parse_feed_selector(value: &str) -> Result<FeedSelector> {
+    if let Ok(id) = value.parse() {
+        return Ok(FeedSelector::ID(id));
+    }
+
+    FeedSelector::parse(value).ok_or_else(|| anyhow!("invalid feed selector: {}", value))
+}
+
+/// Parses a date (such as 2021-01-31), a date and time in RFC 3339 format, or a duration to go back from the current time.
+fn parse_since(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Local
+            .from_local_date(&date)
+            .and_hms_opt(0, 0, 0)
+            .earliest()
+            .map(|time| time.with_timezone(&Utc))
+            .ok_or_else(|| anyhow!("invalid date: {}", value));
+    }
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    match parse_duration(value) {
+        Ok(duration) => time_ago(duration),
+        Err(_) => Err(anyhow!("invalid date or duration: {}", value)),
+    }
+}
+
+fn parse_duration(value: &str) -> Result<Duration> {
+    let unit_pos = value
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(value.len());
+
+    let amount: i64 = value[..unit_pos]
+        .parse()
+        .with_context(|| anyhow!("invalid duration: {}", value))?;
+
+    let unit_secs = match &value[unit_pos..] {
+        "s" => 1,
+        "m" | "" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        unit => return Err(anyhow!("unknown duration unit: {}", unit)),
+    };
+
+    // Durations are stored in milliseconds, so larger values would panic
+    amount
+        .checked_mul(unit_secs)
+        .filter(|&secs| secs <= i64::MAX / 1000)
+        .map(Duration::seconds)
+        .ok_or_else(|| anyhow!("duration is too long: {}", value))
+}
+
+/// Returns the time `duration` ago from the current time.
+fn time_ago(duration: Duration) -> Result<DateTime<Utc>> {
+    Utc::now()
+        .checked_sub_signed(duration)
+        .ok_or_else(|| anyhow!("duration goes back too far: {}s", duration.num_seconds()))
+}
+
 fn main() -> Result<()> {
-    let args = CmdOptions::from_env();
-    let result = run(args);
+    let mut args = CmdOptions::from_env()?;
+
+    if let Some(command) = args.command.take() {
+        return command.run();
+    }
+
+    let result = if args.once {
+        run_once(args)
+    } else {
+        run(args).map(|()| true)
+    };
 
     if let Err(err) = &result {
-        err::error_notif(err);
+        bcnotif::error_notif(err);
+
+        // The database may not be the reason we failed, so it's still worth trying to record the error
+        if let Ok(db) = Database::open() {
+            HistoryEntry::record_error(&db, err, &Utc::now()).ok();
+        }
     }
 
-    result
+    match result {
+        // Lets scripts tell an update that had nothing to show apart from one that failed
+        Ok(false) => process::exit(2),
+        result => result.map(|_| ()),
+    }
 }
 
 fn run(args: CmdOptions) -> Result<()> {
     let config = {
         let cfg = Config::load_or_new().context("failed to load / create config")?;
+        logger::init(&cfg.log, args.log_level).context("failed to set up logging")?;
         Arc::new(Mutex::new(cfg))
     };
 
     let db = Database::open().context("failed to open feed database")?;
 
-    let mut listener_stats = ListenerStatMap::with_capacity(200);
-    let mut remove_old_feeds_time = Utc::now();
+    let mut updater = Updater::new(db);
+    let mut next_cleanup_time = Utc::now();
+    let mut last_update_time: Option<DateTime<Utc>> = None;
 
-    let event_rx = Event::init_threads(&config).context("failed to init event threads")?;
+    // The socket is removed when this is dropped, which includes returning early from an error
+    let (event_tx, event_rx, _socket) =
+        Event::init_threads().context("failed to init event threads")?;
+
+    if let Some(addr) = config.lock().metrics.listen {
+        bcnotif::serve_metrics(addr).context("failed to start metrics endpoint")?;
+    }
+
+    if args.reload_config {
+        Event::spawn_config_watcher(event_tx.clone()).context("config watcher spawn failed")?;
+    }
+
+    let scheduler = Event::spawn_scheduler(event_tx.clone(), &config.lock());
+
+    info!(
+        "started, updating every {} minutes",
+        config.lock().misc.update_time_mins
+    );
+
+    let mut notifier = DesktopNotifier::new(
+        config.lock().notification.mode,
+        notif_event_handler(&event_tx),
+    );
+
+    let mut output = args.output;
 
     loop {
         match event_rx.recv() {
             Ok(Event::RunUpdate) => {
                 let cur_time = Utc::now();
-                let mut config = config.lock();
 
-                if args.reload_config {
-                    match Config::load() {
-                        Ok(new) => *config = new,
-                        Err(err) => err::error_notif(&err),
+                if let Some(last_update) = last_update_time {
+                    let elapsed = (cur_time - last_update).to_std().unwrap_or_default();
+
+                    if scheduler.is_gap(elapsed) {
+                        info!(
+                            "{} minutes passed since the last update, resetting feed statistics",
+                            elapsed.as_secs() / 60
+                        );
+
+                        if let Err(err) = updater.reset_stats() {
+                            updater.report_error(&err);
+                        }
                     }
                 }
 
-                let result = run_update(&db, &config, &cur_time, &mut listener_stats).and_then(
-                    |mut notifs| {
-                        FeedNotif::sort_all(&mut notifs, &config);
-                        FeedNotif::show_all(&notifs)
-                    },
-                );
+                last_update_time = Some(cur_time);
+                let config = config.lock();
 
-                if let Err(err) = result {
-                    err::error_notif(&err);
+                // The summary shouldn't have to wait for feeds to be scraped successfully again
+                if let Err(err) = updater.flush_quiet_queue(&config, &mut notifier, &cur_time) {
+                    updater.report_error(&err);
+                }
+
+                match updater.update(&config, &cur_time) {
+                    Ok(mut update) => {
+                        scheduler.set_spiking(update.feeds.iter().any(|feed| feed.has_spiked));
+
+                        if let Err(err) =
+                            updater.notify(&mut update, &config, &mut notifier, &cur_time)
+                        {
+                            updater.report_error(&err);
+                        }
+
+                        if let Some(format) = output {
+                            // This usually means whatever was reading our output has exited, so there's no point in trying again
+                            if let Err(err) = format.print(&update.feeds) {
+                                output = None;
+                                updater.report_error(&err.context(
+                                    "printing feeds failed, so they will no longer be printed",
+                                ));
+                            }
+                        }
+                    }
+                    Err(err) => updater.report_error(&err),
                 }
 
-                if cur_time >= remove_old_feeds_time {
-                    ListenerAvg::remove_old_from_db(&db)?;
-                    remove_old_feeds_time = cur_time + Duration::hours(12);
+                if cur_time >= next_cleanup_time {
+                    if let Err(err) = updater.remove_old_data(&config.database) {
+                        updater.report_error(&err);
+                    }
+
+                    let interval_secs = config.database.cleanup_interval_hours * 60.0 * 60.0;
+                    next_cleanup_time =
+                        cur_time + Duration::seconds(interval_secs.max(60.0) as i64);
+                }
+
+                scheduler.update_finished();
+            }
+            Ok(Event::Resumed) => {
+                info!("system resumed from sleep, resetting feed statistics");
+                if let Err(err) = updater.reset_stats() {
+                    updater.report_error(&err);
+                }
+
+                // The next update would otherwise see the same gap and reset them again
+                last_update_time = None;
+            }
+            Ok(Event::MuteFeed(id)) => {
+                let hours = config.lock().notification.mute_hours;
+
+                if let Err(err) = updater.mute(id, hours) {
+                    updater.report_error(&err);
+                }
+            }
+            Ok(Event::Control(request, reply)) => {
+                let is_shutdown = matches!(request, Request::Shutdown);
+
+                let response = match request {
+                    Request::Update => {
+                        event_tx.send(Event::RunUpdate).ok();
+                        Response::Ok
+                    }
+                    Request::Reload => {
+                        Response::from_result(reload_config(&config, &scheduler, &mut notifier))
+                    }
+                    Request::Mute { id, hours } => {
+                        let hours = hours.unwrap_or_else(|| config.lock().notification.mute_hours);
+                        Response::from_result(updater.mute(id, hours))
+                    }
+                    Request::Unmute { id } => Response::from_result(updater.unmute(id)),
+                    Request::Stats => Response::Stats {
+                        feeds: updater
+                            .stats()
+                            .map(|(id, stats)| FeedState {
+                                id,
+                                average: stats.average.current,
+                                unskewed_average: stats.unskewed_average,
+                                jump: stats.jump,
+                                has_spiked: stats.has_spiked,
+                                spike_count: stats.spike_count,
+                                escalation: stats.escalation,
+                            })
+                            .collect(),
+                    },
+                    Request::Shutdown => Response::Ok,
+                };
+
+                reply.send(response).ok();
+
+                if is_shutdown {
+                    info!("shutting down by request");
+                    break Ok(());
                 }
             }
-            Ok(Event::Exit) => break Ok(()),
+            Ok(Event::ReloadConfig) => {
+                if let Err(err) = reload_config(&config, &scheduler, &mut notifier) {
+                    updater.report_error(&err);
+                }
+            }
+            Ok(Event::Exit) => {
+                info!("exiting");
+                break Ok(());
+            }
             Err(err) => break Err(err.into()),
         }
     }
 }
 
+/// Runs a single update using the statistics saved from previous runs, then exits.
+///
+/// Notifications are shown without actions, since the program won't be around to handle them.
+/// Returns true if any feeds were shown.
+fn run_once(args: CmdOptions) -> Result<bool> {
+    let config = Config::load_or_new().context("failed to load / create config")?;
+    logger::init(&config.log, args.log_level).context("failed to set up logging")?;
+
+    let db = Database::open().context("failed to open feed database")?;
+
+    let mut updater = Updater::new(db);
+    let cur_time = Utc::now();
+    let mut update = updater.update(&config, &cur_time)?;
+
+    let shown = updater.notify(
+        &mut update,
+        &config,
+        &mut DesktopNotifier::without_actions(config.notification.mode),
+        &cur_time,
+    )?;
+
+    match args.output {
+        Some(output) => output.print(&update.feeds)?,
+        None => {
+            let num_shown = shown.len() as u32;
+
+            for (i, notif) in shown.iter().enumerate() {
+                println!("{}", notif.digest_line(1 + i as u32, num_shown, &config));
+            }
+        }
+    }
+
+    // There's no later update to show queued notifications in, so they're always dropped
+    if updater.num_queued() > 0 {
+        info!(
+            "dropped {} notifications held back by quiet hours",
+            updater.num_queued()
+        );
+    }
+
+    updater.remove_old_data(&config.database)?;
+    Ok(!shown.is_empty())
+}
+
+/// Replaces the current config with the one on disk, leaving it untouched if the new one can't be loaded.
+fn reload_config(
+    config: &Mutex<Config>,
+    scheduler: &Scheduler,
+    notifier: &mut DesktopNotifier,
+) -> Result<()> {
+    let new = Config::load().context("failed to reload config")?;
+    logger::apply(&new.log).context("failed to apply new log options")?;
+    scheduler.configure(&new.misc);
+    notifier.set_mode(new.notification.mode);
+    *config.lock() = new;
+
+    info!("reloaded config");
+    Ok(())
+}
+
 enum Event {
     RunUpdate,
+    Resumed,
+    ReloadConfig,
+    MuteFeed(u32),
+    Control(Request, mpsc::Sender<Response>),
     Exit,
 }
 
 impl Event {
-    fn init_threads(config: &Arc<Mutex<Config>>) -> Result<mpsc::Receiver<Self>> {
+    fn init_threads() -> Result<(mpsc::Sender<Self>, mpsc::Receiver<Self>, ctl::Socket)> {
         let (tx, rx) = mpsc::channel();
 
-        Self::spawn_update_thread(tx.clone(), config);
-        Self::spawn_signal_handler(tx).context("signal handler spawn failed")?;
+        // The signal handler must be spawned first so signals are blocked on every other thread
+        Self::spawn_signal_handler(tx.clone()).context("signal handler spawn failed")?;
+        Self::spawn_sleep_watcher(tx.clone());
+        let socket = Self::spawn_ctl_server(tx.clone()).context("control socket spawn failed")?;
 
-        Ok(rx)
+        Ok((tx, rx, socket))
     }
 
-    fn spawn_update_thread(
-        tx: mpsc::Sender<Self>,
-        config: &Arc<Mutex<Config>>,
-    ) -> thread::JoinHandle<()> {
-        let config = config.clone();
-
-        // This thread should die if something goes horribly wrong, so the uses of unwrap() are intended here
-        thread::spawn(move || loop {
-            let update_time = {
-                let config = config.lock();
-                (config.misc.update_time_mins * 60.0) as u64
-            };
-
-            if tx.send(Event::RunUpdate).is_err() {
-                break;
-            }
+    fn spawn_config_watcher(tx: mpsc::Sender<Self>) -> Result<()> {
+        Config::watch(move || tx.send(Event::ReloadConfig).is_ok())
+    }
 
-            thread::sleep(std::time::Duration::from_secs(update_time));
+    fn spawn_ctl_server(tx: mpsc::Sender<Self>) -> Result<ctl::Socket> {
+        ctl::listen(move |request| {
+            let (reply_tx, reply_rx) = mpsc::channel();
+            tx.send(Event::Control(request, reply_tx)).ok()?;
+            reply_rx.recv().ok()
         })
     }
 
-    fn spawn_signal_handler(tx: mpsc::Sender<Self>) -> Result<()> {
-        use nix::sys::signal::{signal, SigHandler, Signal};
+    fn spawn_scheduler(tx: mpsc::Sender<Self>, config: &Config) -> Arc<Scheduler> {
+        Scheduler::spawn(&config.misc, move || tx.send(Event::RunUpdate).is_ok())
+    }
 
-        static SIG_TRIGGER: Lazy<(Mutex<()>, Condvar)> =
-            Lazy::new(|| (Mutex::new(()), Condvar::new()));
+    /// Listens for the system resuming from sleep through logind.
+    ///
+    /// Resumes are also caught by the gap between updates, so this is only used when logind is available.
+    fn spawn_sleep_watcher(tx: mpsc::Sender<Self>) {
+        use dbus::blocking::Connection;
+        use dbus::message::MatchRule;
 
-        extern "C" fn handle_sig(_: libc::c_int) {
-            let (_, cvar) = &*SIG_TRIGGER;
-            cvar.notify_one();
-        }
+        thread::spawn(move || {
+            let conn = match Connection::new_system() {
+                Ok(conn) => conn,
+                Err(err) => {
+                    debug!(
+                        "not watching for sleep, failed to connect to the system bus: {}",
+                        err
+                    );
+                    return;
+                }
+            };
 
-        let handler = SigHandler::Handler(handle_sig);
-        let sigs = [Signal::SIGHUP, Signal::SIGTERM, Signal::SIGINT];
+            let rule = MatchRule::new_signal("org.freedesktop.login1.Manager", "PrepareForSleep");
 
-        unsafe {
-            for &sig in &sigs {
-                signal(sig, handler)
-                    .map_err(|err| anyhow!("failed to register signal handler: {}", err))?;
+            // The signal is sent with true before sleeping, and false after resuming
+            let result = conn.add_match(rule, move |(sleeping,): (bool,), _, _| {
+                sleeping || tx.send(Event::Resumed).is_ok()
+            });
+
+            if let Err(err) = result {
+                debug!(
+                    "not watching for sleep, failed to listen to logind: {}",
+                    err
+                );
+                return;
             }
-        }
 
-        thread::spawn(move || {
-            let (lock, cvar) = &*SIG_TRIGGER;
-            cvar.wait(&mut lock.lock());
-            tx.send(Event::Exit).ok();
+            while conn.process(std::time::Duration::from_secs(60)).is_ok() {}
         });
-
-        Ok(())
     }
-}
 
-fn run_update<'a>(
-    db: &Database,
-    config: &Config,
-    cur_time: &DateTime<Utc>,
-    listener_stats: &mut ListenerStatMap,
-) -> Result<SmallVec<[FeedNotif<'a>; 3]>> {
-    use diesel::result::Error;
-
-    let feeds = {
-        let mut feeds = Feed::scrape_all(config).context("feed scraping failed")?;
-        filter_feeds(config, &mut feeds);
-        feeds
-    };
+    fn spawn_signal_handler(tx: mpsc::Sender<Self>) -> Result<()> {
+        use nix::sys::signal::{SigSet, Signal};
 
-    let cur_hour = cur_time.hour() as u8;
-    let cur_weekday = Local::today().weekday();
+        let mut sigs = SigSet::empty();
 
-    let mut display = SmallVec::new();
+        for &sig in &[
+            Signal::SIGHUP,
+            Signal::SIGTERM,
+            Signal::SIGINT,
+            Signal::SIGUSR1,
+        ] {
+            sigs.add(sig);
+        }
 
-    db.conn()
-        .transaction::<_, Error, _>(|| {
-            for feed in feeds {
-                let stats = listener_stats.entry(feed.id).or_insert_with(|| {
-                    ListenerStats::init_from_db(db, cur_hour, feed.id as i32, feed.listeners as f32)
-                });
+        // Threads inherit the signal mask of the thread that spawned them, so blocking the signals here
+        // before any other thread is spawned ensures that they will only be received by the thread below
+        sigs.thread_block()
+            .map_err(|err| anyhow!("failed to block signals: {}", err))?;
 
-                stats.update(cur_hour, &feed, config, cur_weekday);
-                stats.save_to_db(db)?;
+        let mut is_exiting = false;
 
-                if !stats.should_display_feed(&feed, config) {
-                    continue;
+        // The signals stay blocked after the first request to exit, so a second one has to be handled here
+        // for it to still be able to stop a shutdown that hangs
+        thread::spawn(move || loop {
+            let sig = match sigs.wait() {
+                Ok(sig) => sig,
+                // Retrying would just spin if the error doesn't go away
+                Err(err) => {
+                    error!(
+                        "failed to wait for signals, they will no longer be handled: {}",
+                        err
+                    );
+                    break;
                 }
+            };
 
-                if display.len() > config.misc.show_max as usize {
-                    continue;
+            let event = match sig {
+                Signal::SIGHUP => Event::ReloadConfig,
+                Signal::SIGUSR1 => Event::RunUpdate,
+                _ if is_exiting => {
+                    warn!("received {} while exiting, exiting immediately", sig);
+                    ctl::remove_socket();
+                    process::exit(128 + sig as i32);
                 }
+                _ => {
+                    is_exiting = true;
+                    Event::Exit
+                }
+            };
 
-                display.push(FeedNotif::new(feed, stats));
-            }
+            tx.send(event).ok();
+        });
 
-            Ok(())
-        })
-        .context("database transaction failed")?;
+        Ok(())
+    }
+}
+
+/// Returns a closure that forwards events from notifications to the event loop.
+fn notif_event_handler(event_tx: &mpsc::Sender<Event>) -> impl FnMut(NotifEvent) + Send + 'static {
+    let event_tx = event_tx.clone();
+
+    move |event| {
+        let event = match event {
+            NotifEvent::Mute(id) => Event::MuteFeed(id),
+        };
 
-    Ok(display)
+        event_tx.send(event).ok();
+    }
 }
 
-fn filter_feeds(config: &Config, feeds: &mut Vec<Feed>) {
-    if !config.filters.whitelist.is_empty() {
-        feeds.retain(|feed| {
-            config
-                .filters
-                .whitelist
-                .iter()
-                .any(|entry| entry.matches_feed(feed))
-        });
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::minutes(90));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::seconds(30));
+        assert_eq!(parse_duration("12h").unwrap(), Duration::hours(12));
+        assert_eq!(parse_duration("3d").unwrap(), Duration::days(3));
+        assert_eq!(parse_duration("2w").unwrap(), Duration::weeks(2));
+        assert!(parse_duration("5y").is_err());
+        assert!(parse_duration("h").is_err());
     }
 
-    if !config.filters.blacklist.is_empty() {
-        feeds.retain(|feed| {
-            config
-                .filters
-                .blacklist
-                .iter()
-                .any(|entry| !entry.matches_feed(feed))
-        });
+    #[test]
+    fn parse_duration_rejects_overflow() {
+        assert!(parse_duration("9223372036854775807w").is_err());
+        assert!(parse_duration("99999999999999999999").is_err());
+        assert!(parse_duration(&format!("{}s", i64::MAX / 1000 + 1)).is_err());
+    }
+
+    #[test]
+    fn parse_since_rejects_times_before_the_epoch_limit() {
+        assert!(parse_since("9000000000000000s").is_err());
+        assert!(parse_since("2021-01-31").is_ok());
+        assert!(parse_since("1d").is_ok());
     }
 }
@@ -0,0 +1,305 @@
+use crate::feed::stats::FeedSample;
+use crate::feed::{NotifKind, Source};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+static METRICS: Lazy<Mutex<Metrics>> = Lazy::new(|| Mutex::new(Metrics::default()));
+
+/// The values exposed on the metrics endpoint.
+#[derive(Default)]
+struct Metrics {
+    feeds: Vec<FeedSample>,
+    scrapes: HashMap<String, ScrapeStats>,
+    notifs: HashMap<&'static str, u64>,
+    transactions: Timing,
+    pruned_rows: HashMap<&'static str, u64>,
+}
+
+#[derive(Default)]
+struct ScrapeStats {
+    timing: Timing,
+    failures: u64,
+}
+
+#[derive(Default)]
+struct Timing {
+    count: u64,
+    total_secs: f64,
+}
+
+impl Timing {
+    fn add(&mut self, duration: Duration) {
+        self.count += 1;
+        self.total_secs += duration.as_secs_f64();
+    }
+}
+
+/// Replaces the feeds exposed on the endpoint with the ones from the latest update.
+pub fn set_feeds(feeds: &[FeedSample]) {
+    METRICS.lock().feeds = feeds.to_vec();
+}
+
+pub fn record_scrape(source: &Source, duration: Duration, succeeded: bool) {
+    let mut metrics = METRICS.lock();
+    let stats = metrics.scrapes.entry(source.name().into()).or_default();
+
+    stats.timing.add(duration);
+
+    if !succeeded {
+        stats.failures += 1;
+    }
+}
+
+pub fn record_notif(kind: NotifKind) {
+    let kind = match kind {
+        NotifKind::Update => "update",
+        NotifKind::Escalating(_) => "escalating",
+        NotifKind::Subsided => "subsided",
+    };
+
+    *METRICS.lock().notifs.entry(kind).or_insert(0) += 1;
+}
+
+pub fn record_transaction(duration: Duration) {
+    METRICS.lock().transactions.add(duration);
+}
+
+/// Records rows removed from `table` while cleaning up the database.
+pub fn record_pruned(table: &'static str, rows: usize) {
+    *METRICS.lock().pruned_rows.entry(table).or_insert(0) += rows as u64;
+}
+
+/// Serves metrics in the Prometheus text format from a new thread.
+pub fn serve(addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .with_context(|| anyhow!("failed to bind metrics endpoint to {}", addr))?;
+
+    info!("serving metrics on http://{}/metrics", addr);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+
+            if let Err(err) = handle_client(stream) {
+                debug!("metrics request failed: {}", err);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_client(stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    // The headers aren't used, but they should be read before responding
+    let mut header = String::new();
+
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") | ("GET", "/") => ("200 OK", METRICS.lock().to_string()),
+        ("GET", _) => ("404 Not Found", "not found\n".into()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".into()),
+    };
+
+    let mut stream = stream;
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+
+    Ok(())
+}
+
+/// Returns a value of a feed to expose, or None if the feed doesn't have one.
+type FeedValueFn = fn(&FeedSample) -> Option<f64>;
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let feed_gauges: [(&str, &str, FeedValueFn); 5] = [
+            (
+                "listeners",
+                "The feed's current number of listeners.",
+                |feed| Some(feed.listeners.into()),
+            ),
+            ("average", "The feed's current listener average.", |feed| {
+                Some(feed.average.into())
+            }),
+            (
+                "unskewed_average",
+                "The feed's listener average before it started to spike consistently.",
+                |feed| feed.unskewed_average.map(Into::into),
+            ),
+            (
+                "jump",
+                "The number of listeners the feed has jumped by.",
+                |feed| Some(feed.jump.into()),
+            ),
+            (
+                "spike_count",
+                "The number of times the feed has spiked consecutively.",
+                |feed| Some(feed.spike_count.into()),
+            ),
+        ];
+
+        for (name, help, value_of) in &feed_gauges {
+            write_header(f, &format!("feed_{}", name), help, "gauge")?;
+
+            for feed in &self.feeds {
+                if let Some(value) = value_of(feed) {
+                    writeln!(
+                        f,
+                        "{}_feed_{}{{id=\"{}\",name=\"{}\",county=\"{}\",location=\"{}\"}} {}",
+                        env!("CARGO_PKG_NAME"),
+                        name,
+                        feed.id,
+                        escape(&feed.name),
+                        escape(&feed.county),
+                        feed.location,
+                        value
+                    )?;
+                }
+            }
+        }
+
+        write_header(
+            f,
+            "scrape_duration_seconds",
+            "The time spent scraping feeds from each source.",
+            "summary",
+        )?;
+
+        for (source, stats) in &self.scrapes {
+            let labels = format!("{{source=\"{}\"}}", escape(source));
+            write_timing(f, "scrape_duration_seconds", &labels, &stats.timing)?;
+        }
+
+        write_header(
+            f,
+            "scrape_failures_total",
+            "The number of times scraping feeds from each source has failed.",
+            "counter",
+        )?;
+
+        for (source, stats) in &self.scrapes {
+            writeln!(
+                f,
+                "{}_scrape_failures_total{{source=\"{}\"}} {}",
+                env!("CARGO_PKG_NAME"),
+                escape(source),
+                stats.failures
+            )?;
+        }
+
+        write_header(
+            f,
+            "notifications_total",
+            "The number of notifications shown for feeds of each kind.",
+            "counter",
+        )?;
+
+        for (kind, count) in &self.notifs {
+            writeln!(
+                f,
+                "{}_notifications_total{{kind=\"{}\"}} {}",
+                env!("CARGO_PKG_NAME"),
+                kind,
+                count
+            )?;
+        }
+
+        write_header(
+            f,
+            "db_transaction_duration_seconds",
+            "The time spent saving feed statistics to the database during each update.",
+            "summary",
+        )?;
+        write_timing(f, "db_transaction_duration_seconds", "", &self.transactions)?;
+
+        write_header(
+            f,
+            "db_pruned_rows_total",
+            "The number of old rows removed from each database table.",
+            "counter",
+        )?;
+
+        for (table, count) in &self.pruned_rows {
+            writeln!(
+                f,
+                "{}_db_pruned_rows_total{{table=\"{}\"}} {}",
+                env!("CARGO_PKG_NAME"),
+                table,
+                count
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_header(f: &mut fmt::Formatter, name: &str, help: &str, kind: &str) -> fmt::Result {
+    writeln!(f, "# HELP {}_{} {}", env!("CARGO_PKG_NAME"), name, help)?;
+    writeln!(f, "# TYPE {}_{} {}", env!("CARGO_PKG_NAME"), name, kind)
+}
+
+fn write_timing(f: &mut fmt::Formatter, name: &str, labels: &str, timing: &Timing) -> fmt::Result {
+    writeln!(
+        f,
+        "{}_{}_sum{} {}",
+        env!("CARGO_PKG_NAME"),
+        name,
+        labels,
+        timing.total_secs
+    )?;
+
+    writeln!(
+        f,
+        "{}_{}_count{} {}",
+        env!("CARGO_PKG_NAME"),
+        name,
+        labels,
+        timing.count
+    )
+}
+
+/// Escapes a label value as described by the Prometheus text format.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for ch in value.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            ch => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,377 @@
+use crate::config::{Config, NotifMode};
+use crate::feed::{FeedNotif, NotifEvent, Severity};
+use anyhow::{anyhow, Context, Result};
+use dbus::arg::Variant;
+use dbus::blocking::LocalConnection;
+use dbus::message::MatchRule;
+use notify_rust::{Notification, Urgency};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::process::Command;
+use std::rc::Rc;
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// Shows notifications for feeds.
+///
+/// The daemon shows desktop notifications with `DesktopNotifier`, but feeds can be sent anywhere else by implementing this.
+pub trait Notifier {
+    /// Shows notifications for the feeds from an update, in the order they're given in.
+    fn notify(&mut self, notifs: &[FeedNotif], config: &Config) -> Result<()>;
+
+    /// Shows a single notification summarizing feeds that were held back, such as during quiet hours.
+    fn notify_summary(&mut self, notifs: &[FeedNotif], title: &str, config: &Config) -> Result<()>;
+}
+
+/// Shows feeds as desktop notifications.
+pub struct DesktopNotifier {
+    mode: NotifMode,
+    worker: Option<mpsc::Sender<Job>>,
+}
+
+impl DesktopNotifier {
+    /// Creates a notifier that adds actions to each notification and calls `on_event` when one is chosen.
+    ///
+    /// Notifications are shown from a single thread that lives as long as the notifier, which is also where
+    /// `on_event` is called from.
+    pub fn new<F>(mode: NotifMode, on_event: F) -> Self
+    where
+        F: FnMut(NotifEvent) + Send + 'static,
+    {
+        Self {
+            mode,
+            worker: Some(Worker::spawn(on_event)),
+        }
+    }
+
+    /// Creates a notifier that shows notifications without actions, for when nothing will be around to handle them.
+    pub fn without_actions(mode: NotifMode) -> Self {
+        Self { mode, worker: None }
+    }
+
+    /// Changes how the feeds from an update are shown, such as after the config is reloaded.
+    pub fn set_mode(&mut self, mode: NotifMode) {
+        self.mode = mode;
+    }
+
+    fn show(&self, notif: &FeedNotif, index: u32, max_index: u32, config: &Config) -> Result<()> {
+        let worker = match &self.worker {
+            Some(worker) => worker,
+            None => {
+                return notif
+                    .build_notif(index, max_index, config)
+                    .show()
+                    .map_err(|err| anyhow!("failed to create notification: {}", err))
+                    .map(|_| ())
+            }
+        };
+
+        let mute_label = format!("Mute for {} hours", config.notification.mute_hours);
+        let mut desktop_notif = notif.build_notif(index, max_index, config);
+
+        desktop_notif
+            .action("open", "Open feed")
+            .action("mute", &mute_label);
+
+        let (reply_tx, reply_rx) = mpsc::channel();
+
+        let job = Job {
+            notif: desktop_notif,
+            urgency: notif.severity(config).urgency(),
+            feed_id: notif.feed.id,
+            url: notif.feed.url(),
+            reply: reply_tx,
+        };
+
+        // Waiting for each notification to be shown keeps them in order
+        worker
+            .send(job)
+            .map_err(|_| anyhow!("notification thread has stopped"))?;
+
+        reply_rx
+            .recv()
+            .map_err(|_| anyhow!("notification thread has stopped"))?
+    }
+
+    /// Shows a single notification with a line for each feed, using the most severe feed's urgency.
+    ///
+    /// Only the first `max_lines` feeds are listed if it's set, followed by a count of the rest.
+    fn show_digest(
+        notifs: &[FeedNotif],
+        title: &str,
+        max_lines: Option<u32>,
+        config: &Config,
+    ) -> Result<()> {
+        let num_notifs = notifs.len() as u32;
+        let max_lines = max_lines.map_or(notifs.len(), |max| max as usize);
+
+        let mut lines = notifs
+            .iter()
+            .take(max_lines)
+            .enumerate()
+            .map(|(i, notif)| notif.digest_line(1 + i as u32, num_notifs, config))
+            .collect::<Vec<_>>();
+
+        let num_hidden = notifs.len() - lines.len();
+
+        if num_hidden > 0 {
+            lines.push(format!("and {} more", num_hidden));
+        }
+
+        let severity = notifs
+            .iter()
+            .map(|notif| notif.severity(config))
+            .max()
+            .unwrap_or(Severity::Low);
+
+        Notification::new()
+            .summary(title)
+            .body(&lines.join("\n"))
+            .urgency(severity.urgency())
+            .timeout(severity.timeout())
+            .icon(severity.icon())
+            .show()
+            .map_err(|err| anyhow!("failed to create notification: {}", err))
+            .map(|_| ())
+    }
+}
+
+impl Notifier for DesktopNotifier {
+    fn notify(&mut self, notifs: &[FeedNotif], config: &Config) -> Result<()> {
+        if notifs.is_empty() {
+            return Ok(());
+        }
+
+        let num_notifs = notifs.len() as u32;
+
+        match self.mode {
+            NotifMode::Individual => {
+                for (i, notif) in notifs.iter().enumerate() {
+                    self.show(notif, 1 + i as u32, num_notifs, config)?;
+                }
+
+                Ok(())
+            }
+            NotifMode::Digest => {
+                let title = format!(
+                    concat!(env!("CARGO_PKG_NAME"), " update ({} feeds)"),
+                    num_notifs
+                );
+
+                let max_lines = config.notification.digest_max_lines;
+                Self::show_digest(notifs, &title, Some(max_lines), config)
+            }
+        }
+    }
+
+    /// Lists every feed, since they would otherwise never be seen.
+    fn notify_summary(&mut self, notifs: &[FeedNotif], title: &str, config: &Config) -> Result<()> {
+        Self::show_digest(notifs, title, None, config)
+    }
+}
+
+/// A notification for the worker to show, along with what its actions apply to.
+struct Job {
+    notif: Notification,
+    urgency: Urgency,
+    feed_id: u32,
+    url: String,
+    reply: mpsc::Sender<Result<()>>,
+}
+
+/// A signal about a notification shown by the worker.
+enum Signal {
+    ActionInvoked(u32, String),
+    Closed(u32),
+}
+
+/// Shows notifications with actions and listens for the actions chosen on them, all from one thread.
+///
+/// Notifications are sent through the worker's own D-Bus connection, since some notification servers only send their
+/// signals to the connection that showed the notification.
+struct Worker<F> {
+    on_event: F,
+    conn: Option<LocalConnection>,
+    signals: Rc<RefCell<Vec<Signal>>>,
+    /// The feed ID and URL of every notification that can still have an action chosen, by notification ID.
+    pending: HashMap<u32, (u32, String)>,
+}
+
+impl<F> Worker<F>
+where
+    F: FnMut(NotifEvent) + Send + 'static,
+{
+    const INTERFACE: &'static str = "org.freedesktop.Notifications";
+    const PATH: &'static str = "/org/freedesktop/Notifications";
+
+    /// How long to wait for signals before checking for new notifications to show.
+    const POLL_INTERVAL: Duration = Duration::from_millis(250);
+    /// The most notifications to listen for actions on. The oldest ones are forgotten past this.
+    const MAX_PENDING: usize = 64;
+
+    fn spawn(on_event: F) -> mpsc::Sender<Job> {
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut worker = Self {
+                on_event,
+                conn: None,
+                signals: Rc::new(RefCell::new(Vec::new())),
+                pending: HashMap::new(),
+            };
+
+            worker.run(&rx);
+        });
+
+        tx
+    }
+
+    fn run(&mut self, jobs: &mpsc::Receiver<Job>) {
+        loop {
+            // There's nothing to listen for until a notification is shown
+            let job = if self.pending.is_empty() {
+                match jobs.recv() {
+                    Ok(job) => Some(job),
+                    Err(_) => break,
+                }
+            } else {
+                match jobs.try_recv() {
+                    Ok(job) => Some(job),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            };
+
+            match job {
+                Some(job) => {
+                    let result = self.show(&job);
+                    job.reply.send(result).ok();
+                }
+                None => self.process_signals(),
+            }
+        }
+    }
+
+    fn show(&mut self, job: &Job) -> Result<()> {
+        match self.send_notify(job) {
+            Ok(id) => {
+                if self.pending.len() >= Self::MAX_PENDING {
+                    if let Some(&oldest) = self.pending.keys().min() {
+                        self.pending.remove(&oldest);
+                    }
+                }
+
+                self.pending.insert(id, (job.feed_id, job.url.clone()));
+                Ok(())
+            }
+            Err(err) => {
+                // Actions can't arrive for notifications shown through a lost connection
+                self.pending.clear();
+                Err(err.context("failed to create notification"))
+            }
+        }
+    }
+
+    fn send_notify(&mut self, job: &Job) -> Result<u32> {
+        let conn = match self.conn.take() {
+            Some(conn) => conn,
+            None => self
+                .connect()
+                .context("failed to connect to the session bus")?,
+        };
+
+        let notif = &job.notif;
+        let timeout: i32 = notif.timeout.into();
+
+        let mut hints = HashMap::new();
+        hints.insert("urgency", Variant(job.urgency as u8));
+
+        let result: Result<(u32,), dbus::Error> = conn
+            .with_proxy(Self::INTERFACE, Self::PATH, Duration::from_secs(2))
+            .method_call(
+                Self::INTERFACE,
+                "Notify",
+                (
+                    notif.appname.as_str(),
+                    0u32,
+                    notif.icon.as_str(),
+                    notif.summary.as_str(),
+                    notif.body.as_str(),
+                    notif.actions.clone(),
+                    hints,
+                    timeout,
+                ),
+            );
+
+        // The connection is dropped on errors, since it may have been lost
+        let (id,) = result?;
+        self.conn = Some(conn);
+
+        Ok(id)
+    }
+
+    fn connect(&self) -> Result<LocalConnection> {
+        let conn = LocalConnection::new_session()?;
+
+        let signals = Rc::clone(&self.signals);
+
+        conn.add_match(
+            MatchRule::new_signal(Self::INTERFACE, "ActionInvoked"),
+            move |(id, action): (u32, String), _, _| {
+                signals.borrow_mut().push(Signal::ActionInvoked(id, action));
+                true
+            },
+        )?;
+
+        let signals = Rc::clone(&self.signals);
+
+        conn.add_match(
+            MatchRule::new_signal(Self::INTERFACE, "NotificationClosed"),
+            move |(id, _): (u32, u32), _, _| {
+                signals.borrow_mut().push(Signal::Closed(id));
+                true
+            },
+        )?;
+
+        Ok(conn)
+    }
+
+    fn process_signals(&mut self) {
+        let is_connected = match &self.conn {
+            Some(conn) => conn.process(Self::POLL_INTERVAL).is_ok(),
+            None => false,
+        };
+
+        if !is_connected {
+            // Actions can't arrive without a connection
+            self.conn = None;
+            self.pending.clear();
+            return;
+        }
+
+        let signals = self.signals.borrow_mut().drain(..).collect::<Vec<_>>();
+
+        for signal in signals {
+            match signal {
+                Signal::ActionInvoked(id, action) => {
+                    let (feed_id, url) = match self.pending.remove(&id) {
+                        Some(pending) => pending,
+                        None => continue,
+                    };
+
+                    match action.as_str() {
+                        "open" => {
+                            Command::new("xdg-open").arg(url).spawn().ok();
+                        }
+                        "mute" => (self.on_event)(NotifEvent::Mute(feed_id)),
+                        _ => (),
+                    }
+                }
+                Signal::Closed(id) => {
+                    self.pending.remove(&id);
+                }
+            }
+        }
+    }
+}
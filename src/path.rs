@@ -1,11 +1,16 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use nix::unistd::getuid;
 use once_cell::sync::Lazy;
+use std::env;
 use std::fs;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
 use std::path::PathBuf;
 
 pub enum FilePath {
     Config,
     LocalData,
+    Runtime,
 }
 
 impl FilePath {
@@ -26,6 +31,10 @@ impl FilePath {
         let dir = match self {
             Self::Config => CONFIG_DIR.clone(),
             Self::LocalData => LOCAL_DATA_PATH.clone(),
+            Self::Runtime => match dirs_next::runtime_dir() {
+                Some(dir) => dir,
+                None => return private_temp_dir(),
+            },
         };
 
         if !dir.exists() {
@@ -35,3 +44,31 @@ impl FilePath {
         Ok(dir)
     }
 }
+
+/// Returns a directory in the temp directory that only the current user can access, creating it if needed.
+///
+/// This is used in place of a missing runtime directory, since any user can create files in the temp directory
+/// itself. An existing directory is only used if it belongs to the current user and no one else can access it.
+fn private_temp_dir() -> Result<PathBuf> {
+    let uid = getuid();
+
+    let mut dir = env::temp_dir();
+    dir.push(format!("{}-{}", env!("CARGO_PKG_NAME"), uid));
+
+    match fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(err) => return Err(err).context("dir creation failed"),
+    }
+
+    let metadata = fs::symlink_metadata(&dir).context("failed to read dir metadata")?;
+
+    if !metadata.is_dir() || metadata.uid() != uid.as_raw() || metadata.mode() & 0o077 != 0 {
+        return Err(anyhow!(
+            "{} is not a private directory owned by the current user",
+            dir.display()
+        ));
+    }
+
+    Ok(dir)
+}
@@ -0,0 +1,229 @@
+use bcnotif::config::MiscOptions;
+use chrono::{DateTime, Local};
+use log::debug;
+use parking_lot::{Condvar, Mutex};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Runs updates at intervals aligned to the clock, so an update time of 6 minutes runs them at :00, :06, :12 and so on.
+///
+/// A tick is skipped if the update from the previous one hasn't finished yet, so slow updates can't pile up.
+///
+/// When bounds are set, the interval shortens to the minimum while feeds are spiking and gradually backs off to the maximum
+/// while they're quiet.
+pub struct Scheduler {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+struct State {
+    /// The interval currently used, which only differs from `base_interval` when bounds are set.
+    interval: Duration,
+    base_interval: Duration,
+    min_interval: Duration,
+    max_interval: Duration,
+    jitter_secs: u64,
+    update_pending: bool,
+    reschedule: bool,
+}
+
+impl Scheduler {
+    const BACKOFF_MULT: f32 = 1.5;
+    /// The longest time to wait before checking the clock again.
+    ///
+    /// Waits are measured with a clock that stops while the system is suspended, so without this a tick could be
+    /// missed for up to a full interval after resuming.
+    const MAX_WAIT: Duration = Duration::from_secs(30);
+
+    /// Spawns a thread that calls `run_update` on every tick, starting with one right away.
+    ///
+    /// The thread exits once `run_update` returns false.
+    pub fn spawn<F>(options: &MiscOptions, run_update: F) -> Arc<Self>
+    where
+        F: Fn() -> bool + Send + 'static,
+    {
+        let scheduler = Arc::new(Self {
+            state: Mutex::new(State {
+                interval: Duration::from_secs(0),
+                base_interval: Duration::from_secs(0),
+                min_interval: Duration::from_secs(0),
+                max_interval: Duration::from_secs(0),
+                jitter_secs: 0,
+                update_pending: true,
+                reschedule: false,
+            }),
+            changed: Condvar::new(),
+        });
+
+        scheduler.configure(options);
+
+        let sched = Arc::clone(&scheduler);
+
+        thread::spawn(move || {
+            if !run_update() {
+                return;
+            }
+
+            loop {
+                sched.wait_for_tick();
+
+                if !run_update() {
+                    break;
+                }
+            }
+        });
+
+        scheduler
+    }
+
+    /// Applies the update times and jitter from `options`, rescheduling the next tick if they changed.
+    pub fn configure(&self, options: &MiscOptions) {
+        let base_interval = mins_to_interval(options.update_time_mins);
+        let min_interval = options
+            .min_update_time_mins
+            .map_or(base_interval, mins_to_interval)
+            .min(base_interval);
+        let max_interval = options
+            .max_update_time_mins
+            .map_or(base_interval, mins_to_interval)
+            .max(base_interval);
+
+        let mut state = self.state.lock();
+
+        if state.base_interval == base_interval
+            && state.min_interval == min_interval
+            && state.max_interval == max_interval
+            && state.jitter_secs == options.update_jitter_secs
+        {
+            return;
+        }
+
+        state.base_interval = base_interval;
+        state.min_interval = min_interval;
+        state.max_interval = max_interval;
+        state.jitter_secs = options.update_jitter_secs;
+        state.set_interval(base_interval);
+
+        self.changed.notify_one();
+    }
+
+    /// Adjusts the interval to the activity seen in the last update.
+    ///
+    /// While any feed is spiking, the minimum interval is used. Otherwise, the interval returns to the configured update time
+    /// and then grows by half on each quiet update until it reaches the maximum.
+    pub fn set_spiking(&self, spiking: bool) {
+        let mut state = self.state.lock();
+
+        let interval = if spiking {
+            state.min_interval
+        } else if state.interval < state.base_interval {
+            state.base_interval
+        } else {
+            state
+                .interval
+                .mul_f32(Self::BACKOFF_MULT)
+                .min(state.max_interval)
+        };
+
+        if interval == state.interval {
+            return;
+        }
+
+        debug!(
+            "{}, updating every {:.1} minutes",
+            if spiking {
+                "feeds are spiking"
+            } else {
+                "feeds are quiet"
+            },
+            interval.as_secs_f32() / 60.0
+        );
+
+        state.set_interval(interval);
+        self.changed.notify_one();
+    }
+
+    /// Returns true if `elapsed` is much longer than the time between updates should ever be, such as after the system was suspended.
+    pub fn is_gap(&self, elapsed: Duration) -> bool {
+        let state = self.state.lock();
+        elapsed > state.max_interval * 2 + Duration::from_secs(state.jitter_secs)
+    }
+
+    /// Lets the next tick run an update. This should be called once an update has finished.
+    pub fn update_finished(&self) {
+        self.state.lock().update_pending = false;
+    }
+
+    /// Blocks until the next tick that should run an update, and marks the update as pending.
+    fn wait_for_tick(&self) {
+        let mut state = self.state.lock();
+
+        'schedule: loop {
+            state.reschedule = false;
+            let tick = state.next_tick(SystemTime::now());
+
+            debug!(
+                "next scheduled update at {}",
+                DateTime::<Local>::from(tick).format("%H:%M:%S")
+            );
+
+            // The clock is checked again after every wakeup, since waits can be cut short or run long from suspends
+            while let Ok(remaining) = tick.duration_since(SystemTime::now()) {
+                if remaining == Duration::from_secs(0) {
+                    break;
+                }
+
+                self.changed
+                    .wait_for(&mut state, remaining.min(Self::MAX_WAIT));
+
+                if state.reschedule {
+                    continue 'schedule;
+                }
+            }
+
+            if state.update_pending {
+                debug!("previous update is still running, skipping this one");
+                continue;
+            }
+
+            state.update_pending = true;
+            return;
+        }
+    }
+}
+
+impl State {
+    fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+        self.reschedule = true;
+    }
+
+    fn next_tick(&self, now: SystemTime) -> SystemTime {
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let interval = self.interval.as_secs().max(1);
+        let next_boundary = (since_epoch.as_secs() / interval + 1) * interval;
+
+        // Too much jitter would bunch updates together
+        let jitter = Duration::from_secs(self.jitter_secs).min(self.interval / 2);
+
+        UNIX_EPOCH + Duration::from_secs(next_boundary) + random_up_to(jitter)
+    }
+}
+
+fn mins_to_interval(mins: f32) -> Duration {
+    Duration::from_secs((mins * 60.0).max(1.0) as u64)
+}
+
+fn random_up_to(max: Duration) -> Duration {
+    if max == Duration::from_secs(0) {
+        return max;
+    }
+
+    // The keys of a RandomState are seeded randomly once per thread and then varied for each new one, which still
+    // gives a different hash every time and is plenty for spreading out requests
+    let value = RandomState::new().build_hasher().finish();
+    Duration::from_millis(value % max.as_millis() as u64)
+}
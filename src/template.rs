@@ -0,0 +1,247 @@
+use serde::de::Visitor;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::result;
+use std::str::FromStr;
+use strum::VariantNames;
+use strum_macros::{EnumString, EnumVariantNames};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TemplateError {
+    #[error("unknown placeholder {{{0}}}, expected one of: {}", Field::VARIANTS.join(", "))]
+    UnknownField(String),
+
+    #[error("unclosed placeholder starting at position {0}")]
+    UnclosedPlaceholder(usize),
+
+    #[error("unexpected '}}' at position {0} (use '}}}}' for a literal brace)")]
+    UnexpectedClosingBrace(usize),
+}
+
+/// All of the values that can be placed in a template.
+#[derive(Copy, Clone, Debug, EnumString, EnumVariantNames, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum Field {
+    Index,
+    MaxIndex,
+    Status,
+    Id,
+    Name,
+    Url,
+    County,
+    Location,
+    LocationName,
+    Listeners,
+    Jump,
+    JumpPcnt,
+    Baseline,
+    Average,
+    UnskewedAverage,
+    SpikeCount,
+    Alert,
+    AlertLine,
+}
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Text(String),
+    Field(Field),
+}
+
+/// A string containing placeholders for values, such as `{name} has {listeners} listeners`.
+///
+/// Literal braces can be inserted by doubling them.
+#[derive(Clone, Debug)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse<S>(value: S) -> result::Result<Self, TemplateError>
+    where
+        S: AsRef<str>,
+    {
+        let value = value.as_ref();
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = value.char_indices().peekable();
+
+        while let Some((pos, ch)) = chars.next() {
+            match ch {
+                '{' if matches!(chars.peek(), Some((_, '{'))) => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if matches!(chars.peek(), Some((_, '}'))) => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+
+                    loop {
+                        match chars.next() {
+                            Some((_, '}')) => break,
+                            Some((_, ch)) => name.push(ch),
+                            None => return Err(TemplateError::UnclosedPlaceholder(pos)),
+                        }
+                    }
+
+                    let field = Field::from_str(name.trim())
+                        .map_err(|_| TemplateError::UnknownField(name))?;
+
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(text.split_off(0)));
+                    }
+
+                    segments.push(Segment::Field(field));
+                }
+                '}' => return Err(TemplateError::UnexpectedClosingBrace(pos)),
+                ch => text.push(ch),
+            }
+        }
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+
+        Ok(Self {
+            source: value.into(),
+            segments,
+        })
+    }
+
+    /// Renders the template by replacing every placeholder with the value returned from `value_of`.
+    pub fn render<'a, F>(&self, value_of: F) -> String
+    where
+        F: Fn(Field) -> Cow<'a, str>,
+    {
+        let mut result = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => result.push_str(text),
+                Segment::Field(field) => result.push_str(&value_of(*field)),
+            }
+        }
+
+        result
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TemplateVisitor;
+
+        impl<'de> Visitor<'de> for TemplateVisitor {
+            type Value = Template;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a template string")
+            }
+
+            fn visit_str<E>(self, value: &str) -> result::Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Template::parse(value)
+                    .map_err(|err| E::custom(format!("invalid template \"{}\": {}", value, err)))
+            }
+        }
+
+        deserializer.deserialize_str(TemplateVisitor)
+    }
+}
+
+impl Serialize for Template {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str) -> String {
+        Template::parse(template)
+            .unwrap()
+            .render(|field| match field {
+                Field::Name => "Sacramento Fire".into(),
+                Field::Listeners => "120".into(),
+                _ => "?".into(),
+            })
+    }
+
+    #[test]
+    fn render_fields() {
+        assert_eq!(
+            render("{name} has {listeners} listeners"),
+            "Sacramento Fire has 120 listeners"
+        );
+        assert_eq!(render("{ name }"), "Sacramento Fire");
+        assert_eq!(render("no placeholders"), "no placeholders");
+        assert_eq!(render(""), "");
+    }
+
+    #[test]
+    fn render_escaped_braces() {
+        assert_eq!(render("{{{name}}}"), "{Sacramento Fire}");
+        assert_eq!(render("{{name}}"), "{name}");
+    }
+
+    #[test]
+    fn unknown_field() {
+        match Template::parse("{name} {listners}") {
+            Err(TemplateError::UnknownField(name)) => assert_eq!(name, "listners"),
+            result => panic!("expected unknown field, got {:?}", result),
+        }
+
+        assert!(matches!(
+            Template::parse("{}"),
+            Err(TemplateError::UnknownField(name)) if name.is_empty()
+        ));
+    }
+
+    #[test]
+    fn unterminated_placeholder() {
+        assert!(matches!(
+            Template::parse("{name} has {listeners"),
+            Err(TemplateError::UnclosedPlaceholder(11))
+        ));
+        assert!(matches!(
+            Template::parse("{"),
+            Err(TemplateError::UnclosedPlaceholder(0))
+        ));
+    }
+
+    #[test]
+    fn unexpected_closing_brace() {
+        assert!(matches!(
+            Template::parse("{name}} listeners"),
+            Err(TemplateError::UnexpectedClosingBrace(6))
+        ));
+        assert!(matches!(
+            Template::parse("listeners }"),
+            Err(TemplateError::UnexpectedClosingBrace(10))
+        ));
+    }
+
+    #[test]
+    fn serializes_to_source() {
+        let template = Template::parse("{name}: {{{listeners}}}").unwrap();
+        assert_eq!(
+            serde_json::to_string(&template).unwrap(),
+            r#""{name}: {{{listeners}}}""#
+        );
+    }
+}
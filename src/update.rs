@@ -0,0 +1,473 @@
+use crate::config::{Config, DatabaseOptions, QuietMode};
+use crate::database::Database;
+use crate::err;
+use crate::feed::cooldown::{FeedMute, LastNotif};
+use crate::feed::stats::{FeedSample, ListenerAvg, ListenerStatMap, ListenerStats};
+use crate::feed::{Feed, FeedNotif, NotifKind};
+use crate::history::{HistoryEntry, ListenerSample};
+use crate::metrics;
+use crate::notifier::Notifier;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Local, Utc};
+use diesel::prelude::*;
+use log::{debug, error, info};
+use smallvec::SmallVec;
+use std::time::Instant;
+
+/// The results of an update.
+pub struct Update {
+    /// The feeds to show notifications for, in the order they should be shown in.
+    pub notifs: Vec<FeedNotif<'static>>,
+    /// The state of every feed that was processed.
+    ///
+    /// Feeds are only marked as notified once [`Updater::notify`] has shown them.
+    pub feeds: Vec<FeedSample>,
+}
+
+/// Tracks feeds across updates and decides which ones to show notifications for.
+///
+/// This holds everything that has to live longer than a single update, such as the listener statistics of every feed
+/// and the notifications held back by quiet hours.
+pub struct Updater {
+    db: Database,
+    listener_stats: ListenerStatMap,
+    quiet_queue: Vec<FeedNotif<'static>>,
+}
+
+impl Updater {
+    pub fn new(db: Database) -> Self {
+        Self {
+            db,
+            listener_stats: ListenerStatMap::with_capacity(200),
+            quiet_queue: Vec::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    /// Returns the listener statistics of every feed seen since they were last reset, by feed ID.
+    pub fn stats(&self) -> impl Iterator<Item = (u32, &ListenerStats)> {
+        self.listener_stats.iter().map(|(&id, stats)| (id, stats))
+    }
+
+    /// Forgets the listener statistics of every feed, such as after a long gap between updates.
+    ///
+    /// The saved statistics are removed as well, so each feed starts over from its hourly average the next time it's seen.
+    pub fn reset_stats(&mut self) -> Result<()> {
+        self.listener_stats.clear();
+
+        ListenerStats::remove_all_from_db(&self.db)
+            .context("failed to remove saved feed statistics")?;
+        Ok(())
+    }
+
+    /// Returns the number of notifications held back until quiet hours end.
+    pub fn num_queued(&self) -> usize {
+        self.quiet_queue.len()
+    }
+
+    /// Scrapes feeds from every source specified in `config` and processes them.
+    pub fn update(&mut self, config: &Config, cur_time: &DateTime<Utc>) -> Result<Update> {
+        let feeds = Feed::scrape_all(config).context("feed scraping failed")?;
+        self.process(feeds, config, cur_time)
+    }
+
+    /// Updates the statistics of already scraped feeds and picks the ones to show notifications for.
+    ///
+    /// Nothing is shown here, so the notifications have to be passed to [`Updater::notify`] afterwards.
+    pub fn process(
+        &mut self,
+        mut feeds: Vec<Feed<'static>>,
+        config: &Config,
+        cur_time: &DateTime<Utc>,
+    ) -> Result<Update> {
+        use diesel::result::Error;
+
+        let num_scraped = feeds.len();
+        filter_feeds(config, &mut feeds);
+
+        info!(
+            "scraped {} feeds, {} filtered out",
+            num_scraped,
+            num_scraped - feeds.len()
+        );
+
+        let cur_weekday = cur_time.with_timezone(&Local).weekday();
+
+        let db = &self.db;
+        let listener_stats = &mut self.listener_stats;
+
+        let mut notifs = Vec::new();
+        let mut samples = Vec::with_capacity(feeds.len());
+        let transaction_start = Instant::now();
+
+        db.conn()
+            .transaction::<_, Error, _>(|| {
+                for feed in feeds {
+                    let stats = listener_stats.entry(feed.id).or_insert_with(|| {
+                        ListenerStats::init_from_db(
+                            db,
+                            cur_time,
+                            feed.id as i32,
+                            feed.listeners as f32,
+                            config,
+                        )
+                    });
+
+                    stats.update(cur_time, &feed, config, cur_weekday);
+                    stats.save_to_db(db)?;
+                    samples.push(FeedSample::new(&feed, stats, cur_time));
+
+                    let kind = match stats.notif_kind(&feed, config) {
+                        Some(kind) => kind,
+                        None => continue,
+                    };
+
+                    if FeedMute::is_muted(db, feed.id as i32, cur_time)? {
+                        debug!("feed {} is muted, not showing it", feed.id);
+                        continue;
+                    }
+
+                    if notifs.len() > config.misc.show_max as usize {
+                        debug!("show_max reached, not showing feed {}", feed.id);
+                        continue;
+                    }
+
+                    // Escalations and subsided feeds mark a change in an incident, so they shouldn't be held back
+                    if kind == NotifKind::Update {
+                        if let Some(last) = LastNotif::load(db, feed.id as i32).optional()? {
+                            if !last.allows_repeat(&feed, stats, config, cur_time) {
+                                debug!("feed {} was shown recently, not showing it again", feed.id);
+                                continue;
+                            }
+                        }
+                    }
+
+                    debug!("showing feed {} as {:?}", feed.id, kind);
+                    notifs.push(FeedNotif::new(feed, stats, kind));
+                }
+
+                ListenerSample::record_all(db, &samples)
+            })
+            .context("database transaction failed")?;
+
+        metrics::record_transaction(transaction_start.elapsed());
+        metrics::set_feeds(&samples);
+
+        FeedNotif::sort_all(&mut notifs, config);
+
+        Ok(Update {
+            notifs,
+            feeds: samples,
+        })
+    }
+
+    /// Shows notifications for feeds with `notifier`, unless they're held back by quiet hours.
+    ///
+    /// Held back feeds are queued to be shown by [`Updater::flush_quiet_queue`] if quiet hours are set to queue them.
+    /// `cur_time` should be the same time the notifications were picked at, since it's what their cooldowns start from.
+    ///
+    /// The feeds in `update` that were shown are marked as notified, both in `update` and in their saved samples.
+    /// Returns the notifications that were shown, which doesn't include held back ones.
+    pub fn notify<N>(
+        &mut self,
+        update: &mut Update,
+        config: &Config,
+        notifier: &mut N,
+        cur_time: &DateTime<Utc>,
+    ) -> Result<Vec<FeedNotif<'static>>>
+    where
+        N: Notifier,
+    {
+        let shown = self.show(&update.notifs, config, notifier, cur_time)?;
+
+        for sample in &mut update.feeds {
+            if !shown.iter().any(|notif| notif.feed.id == sample.id) {
+                continue;
+            }
+
+            sample.notified = true;
+
+            ListenerSample::mark_notified(&self.db, sample.id as i32, sample.time)
+                .context("failed to mark listener sample as notified")?;
+        }
+
+        Ok(shown)
+    }
+
+    fn show<N>(
+        &mut self,
+        notifs: &[FeedNotif<'static>],
+        config: &Config,
+        notifier: &mut N,
+        cur_time: &DateTime<Utc>,
+    ) -> Result<Vec<FeedNotif<'static>>>
+    where
+        N: Notifier,
+    {
+        let quiet_hours = &config.quiet_hours;
+
+        if !quiet_hours.is_active(cur_time.with_timezone(&Local)) {
+            notifier.notify(notifs, config)?;
+            self.remember_all(notifs, cur_time)?;
+            self.record_all(notifs, cur_time)?;
+            return Ok(notifs.to_vec());
+        }
+
+        let (allowed, held): (Vec<_>, Vec<_>) = notifs
+            .iter()
+            .cloned()
+            .partition(|notif| quiet_hours.allows(notif));
+
+        debug!(
+            "quiet hours are active, holding back {} of {} notifications",
+            held.len(),
+            held.len() + allowed.len()
+        );
+
+        // Queued feeds aren't put on a cooldown until the queue is shown, since it may never be
+        if quiet_hours.mode == QuietMode::Queue {
+            for notif in held {
+                // Only keep the largest jump for feeds that notify multiple times during quiet hours
+                match self
+                    .quiet_queue
+                    .iter_mut()
+                    .find(|queued| queued.feed == notif.feed)
+                {
+                    Some(queued) if queued.jump < notif.jump => *queued = notif,
+                    Some(_) => (),
+                    None => self.quiet_queue.push(notif),
+                }
+            }
+        }
+
+        notifier.notify(&allowed, config)?;
+        self.remember_all(&allowed, cur_time)?;
+        self.record_all(&allowed, cur_time)?;
+
+        Ok(allowed)
+    }
+
+    /// Shows a summary of the feeds held back by quiet hours once they've ended.
+    ///
+    /// The feeds stay queued if the summary can't be shown, so it can be tried again on the next update.
+    pub fn flush_quiet_queue<N>(
+        &mut self,
+        config: &Config,
+        notifier: &mut N,
+        cur_time: &DateTime<Utc>,
+    ) -> Result<()>
+    where
+        N: Notifier,
+    {
+        if self.quiet_queue.is_empty()
+            || config.quiet_hours.is_active(cur_time.with_timezone(&Local))
+        {
+            return Ok(());
+        }
+
+        let title = format!(
+            concat!(env!("CARGO_PKG_NAME"), " quiet hours summary ({} feeds)"),
+            self.quiet_queue.len()
+        );
+
+        notifier.notify_summary(&self.quiet_queue, &title, config)?;
+        self.remember_all(&self.quiet_queue, cur_time)?;
+        self.record_all(&self.quiet_queue, cur_time)?;
+        self.quiet_queue.clear();
+
+        Ok(())
+    }
+
+    /// Saves the notifications that feeds were shown in, which their cooldown is checked against.
+    ///
+    /// This only happens once they've actually reached the user, so feeds that are held back can still be shown later.
+    fn remember_all(&self, notifs: &[FeedNotif], cur_time: &DateTime<Utc>) -> Result<()> {
+        for notif in notifs {
+            if notif.kind != NotifKind::Subsided {
+                LastNotif::new(&notif.feed, notif.jump, cur_time)
+                    .save_to_db(&self.db)
+                    .context("failed to save last notification")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records notifications that were shown in the notification history.
+    fn record_all(&self, notifs: &[FeedNotif], cur_time: &DateTime<Utc>) -> Result<()> {
+        for notif in notifs {
+            metrics::record_notif(notif.kind);
+
+            HistoryEntry::record_notif(&self.db, notif, cur_time)
+                .context("failed to record notification history")?;
+        }
+
+        Ok(())
+    }
+
+    /// Stops notifications from being shown for the feed with the specified ID for `hours`.
+    pub fn mute(&self, feed_id: u32, hours: u32) -> Result<()> {
+        FeedMute::new(feed_id, hours)
+            .save_to_db(&self.db)
+            .context("failed to mute feed")?;
+
+        Ok(())
+    }
+
+    pub fn unmute(&self, feed_id: u32) -> Result<()> {
+        FeedMute::remove_from_db(&self.db, feed_id as i32).context("failed to unmute feed")?;
+        Ok(())
+    }
+
+    /// Shows an error notification and records it in the notification history.
+    pub fn report_error(&self, err: &anyhow::Error) {
+        error!("{:#}", err);
+        err::error_notif(err);
+        HistoryEntry::record_error(&self.db, err, &Utc::now()).ok();
+    }
+
+    /// Removes data that's older than the configured retention from the database, and reports how much was removed.
+    pub fn remove_old_data(&self, options: &DatabaseOptions) -> Result<()> {
+        let db = &self.db;
+        let mut pruned = SmallVec::<[(&str, usize); 6]>::new();
+
+        if options.listener_avg_retention_days > 0 {
+            let days = options.listener_avg_retention_days;
+
+            let rows = ListenerAvg::remove_old_from_db(db, days)
+                .context("failed to remove old listener averages")?;
+
+            pruned.push(("listener_avgs", rows));
+
+            let rows = ListenerStats::remove_old_from_db(db, days)
+                .context("failed to remove old feed statistics")?;
+
+            pruned.push(("feed_stats", rows));
+        }
+
+        if options.sample_retention_days > 0 {
+            let rows = ListenerSample::remove_old_from_db(db, options.sample_retention_days)
+                .context("failed to remove old listener samples")?;
+
+            pruned.push(("listener_samples", rows));
+        }
+
+        if options.notif_history_retention_days > 0 {
+            let rows = HistoryEntry::remove_old_from_db(db, options.notif_history_retention_days)
+                .context("failed to remove old notification history")?;
+
+            pruned.push(("notif_history", rows));
+        }
+
+        if options.last_notif_retention_days > 0 {
+            let rows = LastNotif::remove_old_from_db(db, options.last_notif_retention_days)
+                .context("failed to remove old notifications")?;
+
+            pruned.push(("last_notifs", rows));
+        }
+
+        let rows = FeedMute::remove_old_from_db(db).context("failed to remove expired mutes")?;
+        pruned.push(("feed_mutes", rows));
+
+        for &(table, rows) in &pruned {
+            metrics::record_pruned(table, rows);
+        }
+
+        let total = pruned.iter().map(|&(_, rows)| rows).sum::<usize>();
+
+        if total == 0 {
+            debug!("no old rows to remove from the database");
+            return Ok(());
+        }
+
+        let summary = pruned
+            .iter()
+            .filter(|&&(_, rows)| rows > 0)
+            .map(|(table, rows)| format!("{} from {}", rows, table))
+            .collect::<Vec<_>>();
+
+        info!("removed old rows from the database: {}", summary.join(", "));
+
+        if options.vacuum {
+            let start = Instant::now();
+            db.vacuum().context("failed to vacuum database")?;
+            debug!("vacuumed database in {:.2}s", start.elapsed().as_secs_f32());
+        }
+
+        Ok(())
+    }
+}
+
+fn filter_feeds(config: &Config, feeds: &mut Vec<Feed>) {
+    if !config.filters.whitelist.is_empty() {
+        feeds.retain(|feed| {
+            config
+                .filters
+                .whitelist
+                .iter()
+                .any(|entry| entry.matches_feed(feed))
+        });
+    }
+
+    if !config.filters.blacklist.is_empty() {
+        feeds.retain(|feed| {
+            config
+                .filters
+                .blacklist
+                .iter()
+                .any(|entry| !entry.matches_feed(feed))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FeedSelector;
+    use crate::feed::Location;
+    use crate::fixtures;
+
+    fn feed(id: u32, location: Location) -> Feed<'static> {
+        Feed {
+            location,
+            ..fixtures::feed(id, 100)
+        }
+    }
+
+    fn ids(feeds: &[Feed]) -> Vec<u32> {
+        feeds.iter().map(|feed| feed.id).collect()
+    }
+
+    #[test]
+    fn whitelist_keeps_matching_feeds() {
+        let mut config = Config::default();
+        config.filters.whitelist = vec![
+            FeedSelector::ID(1),
+            FeedSelector::Location(Location::UsAlaska),
+        ];
+
+        let mut feeds = vec![
+            feed(1, Location::UsTexas),
+            feed(2, Location::UsAlaska),
+            feed(3, Location::UsTexas),
+        ];
+
+        filter_feeds(&config, &mut feeds);
+        assert_eq!(ids(&feeds), vec![1, 2]);
+    }
+
+    #[test]
+    fn blacklist_removes_matching_feeds() {
+        let mut config = Config::default();
+        config.filters.blacklist = vec![FeedSelector::Location(Location::UsAlaska)];
+
+        let mut feeds = vec![feed(1, Location::UsTexas), feed(2, Location::UsAlaska)];
+
+        filter_feeds(&config, &mut feeds);
+        assert_eq!(ids(&feeds), vec![1]);
+    }
+}
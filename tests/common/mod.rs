@@ -0,0 +1,96 @@
+//! Fixtures shared by the integration tests.
+
+// Each test crate only uses some of these
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bcnotif::{Config, Database, Feed, FeedNotif, Location, Notifier, Updater};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Records the feeds it's asked to show instead of showing them.
+#[derive(Default)]
+pub struct RecordingNotifier {
+    pub shown: Vec<u32>,
+    pub summaries: Vec<Vec<u32>>,
+}
+
+impl Notifier for RecordingNotifier {
+    fn notify(&mut self, notifs: &[FeedNotif], _: &Config) -> Result<()> {
+        self.shown.extend(notifs.iter().map(|notif| notif.feed.id));
+        Ok(())
+    }
+
+    fn notify_summary(&mut self, notifs: &[FeedNotif], _: &str, _: &Config) -> Result<()> {
+        self.summaries
+            .push(notifs.iter().map(|notif| notif.feed.id).collect());
+
+        Ok(())
+    }
+}
+
+pub fn database() -> Database {
+    Database::open_at(Path::new(":memory:")).unwrap()
+}
+
+pub fn updater() -> Updater {
+    Updater::new(database())
+}
+
+/// A database file that's removed once dropped, for tests that open it more than once.
+pub struct TempDatabase(PathBuf);
+
+impl TempDatabase {
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("bcnotif-{}-{}.sqlite", name, process::id()));
+        let db = Self(path);
+        db.remove();
+        db
+    }
+
+    pub fn updater(&self) -> Updater {
+        Updater::new(Database::open_at(&self.0).unwrap())
+    }
+
+    fn remove(&self) {
+        for suffix in &["", "-wal", "-shm"] {
+            let mut path = self.0.clone().into_os_string();
+            path.push(suffix);
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+pub fn config(toml: &str) -> Config {
+    toml::from_str(toml).unwrap()
+}
+
+/// The time of the first update in a test.
+pub fn start() -> DateTime<Utc> {
+    Utc.ymd(2021, 3, 1).and_hms(12, 0, 0)
+}
+
+/// The time between updates in a test.
+pub fn step() -> Duration {
+    Duration::minutes(6)
+}
+
+pub fn feed(id: u32, listeners: u32, alert: Option<&str>) -> Feed<'static> {
+    Feed {
+        id,
+        name: format!("Feed {}", id),
+        listeners,
+        location: Location::UsAlaska,
+        county: "Anchorage".into(),
+        alert: alert.map(Into::into),
+    }
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use bcnotif::{Config, Database, ExportFormat, Feed, Updater};
+use common::{database, feed, start, step, updater};
+
+fn named(id: u32, name: &str, listeners: u32, alert: Option<&str>) -> Feed<'static> {
+    Feed {
+        name: name.into(),
+        ..feed(id, listeners, alert)
+    }
+}
+
+/// Returns a database with averages and samples for feeds whose names and alerts need quoting in CSV.
+fn populated() -> Updater {
+    let config = Config::default();
+    let mut updater = updater();
+
+    let feeds = || {
+        vec![
+            named(1, "Fire, EMS", 100, None),
+            named(
+                2,
+                "The \"Main\" Channel",
+                200,
+                Some("Large fire,\nroads closed"),
+            ),
+            named(3, "Police", 300, Some("")),
+        ]
+    };
+
+    for i in 0..3 {
+        updater
+            .process(feeds(), &config, &(start() + step() * i))
+            .unwrap();
+    }
+
+    updater
+}
+
+fn export(db: &Database, averages: bool, format: ExportFormat) -> String {
+    let mut out = Vec::new();
+
+    if averages {
+        bcnotif::export_averages(db, None, None, format, &mut out).unwrap();
+    } else {
+        bcnotif::export_samples(db, None, None, format, &mut out).unwrap();
+    }
+
+    String::from_utf8(out).unwrap()
+}
+
+fn round_trip(averages: bool, format: ExportFormat) {
+    let source = populated();
+    let exported = export(source.database(), averages, format);
+
+    let dest = database();
+    let summary = bcnotif::import(&dest, &exported).unwrap();
+
+    if averages {
+        assert_eq!(summary.averages_read, 3);
+        assert_eq!(summary.averages_merged, 3);
+    } else {
+        assert_eq!(summary.samples_read, 9);
+        assert_eq!(summary.samples_added, 9);
+    }
+
+    assert_eq!(export(&dest, averages, format), exported);
+
+    // Importing the same data again shouldn't change anything
+    let summary = bcnotif::import(&dest, &exported).unwrap();
+    assert_eq!(summary.averages_merged + summary.samples_added, 0);
+}
+
+#[test]
+fn averages_round_trip_through_csv() {
+    round_trip(true, ExportFormat::Csv);
+}
+
+#[test]
+fn averages_round_trip_through_json() {
+    round_trip(true, ExportFormat::Json);
+}
+
+#[test]
+fn samples_round_trip_through_csv() {
+    round_trip(false, ExportFormat::Csv);
+}
+
+#[test]
+fn samples_round_trip_through_json() {
+    round_trip(false, ExportFormat::Json);
+}
+
+#[test]
+fn csv_with_crlf_line_endings_imports() {
+    let source = populated();
+    let exported = export(source.database(), true, ExportFormat::Csv).replace('\n', "\r\n");
+
+    let dest = database();
+    let summary = bcnotif::import(&dest, &exported).unwrap();
+    assert_eq!(summary.averages_merged, 3);
+
+    assert_eq!(
+        export(&dest, true, ExportFormat::Csv),
+        export(source.database(), true, ExportFormat::Csv)
+    );
+}
@@ -0,0 +1,316 @@
+mod common;
+
+use bcnotif::{Config, ExportFormat, NotifKind, Update, Updater};
+use chrono::Utc;
+use common::{config, feed, start, step, updater, RecordingNotifier, TempDatabase};
+
+/// Runs updates at every step from the start time until a feed spikes from 100 to 200 listeners.
+fn spike(updater: &mut Updater, config: &Config, id: u32) -> Update {
+    for i in 0..3 {
+        let update = updater
+            .process(vec![feed(id, 100, None)], config, &(start() + step() * i))
+            .unwrap();
+
+        assert!(update.notifs.is_empty());
+    }
+
+    updater
+        .process(vec![feed(id, 200, None)], config, &(start() + step() * 3))
+        .unwrap()
+}
+
+/// Returns whether or not each saved listener sample was marked as notified, in order of time.
+fn saved_notified(updater: &Updater) -> Vec<bool> {
+    let mut out = Vec::new();
+    bcnotif::export_samples(updater.database(), None, None, ExportFormat::Json, &mut out).unwrap();
+
+    let samples: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+
+    samples
+        .iter()
+        .map(|sample| sample["notified"].as_bool().unwrap())
+        .collect()
+}
+
+#[test]
+fn spiking_feeds_are_shown() {
+    let config = Config::default();
+    let mut updater = updater();
+    let mut notifier = RecordingNotifier::default();
+
+    let mut update = spike(&mut updater, &config, 1);
+
+    assert_eq!(update.notifs.len(), 1);
+    assert_eq!(update.notifs[0].kind, NotifKind::Update);
+    assert!(update.notifs[0].has_spiked);
+
+    let shown = updater
+        .notify(&mut update, &config, &mut notifier, &start())
+        .unwrap();
+
+    assert_eq!(notifier.shown, vec![1]);
+    assert_eq!(shown.len(), 1);
+    assert!(update.feeds[0].notified);
+    assert_eq!(saved_notified(&updater), vec![false, false, false, true]);
+}
+
+#[test]
+fn every_processed_feed_is_sampled() {
+    let config = Config::default();
+    let mut updater = updater();
+
+    let update = updater
+        .process(
+            vec![feed(1, 100, None), feed(2, 200, Some("fire"))],
+            &config,
+            &start(),
+        )
+        .unwrap();
+
+    let ids = update.feeds.iter().map(|feed| feed.id).collect::<Vec<_>>();
+
+    assert_eq!(ids, vec![1, 2]);
+    assert_eq!(update.feeds[1].alert.as_deref(), Some("fire"));
+    assert_eq!(updater.stats().count(), 2);
+
+    updater.reset_stats().unwrap();
+    assert_eq!(updater.stats().count(), 0);
+}
+
+#[test]
+fn filtered_feeds_are_skipped() {
+    let config = config("[filters]\nblacklist = [\"id(2)\"]");
+    let mut updater = updater();
+
+    let update = updater
+        .process(
+            vec![feed(1, 100, Some("fire")), feed(2, 100, Some("fire"))],
+            &config,
+            &start(),
+        )
+        .unwrap();
+
+    assert_eq!(update.feeds.len(), 1);
+    assert_eq!(update.feeds[0].id, 1);
+}
+
+#[test]
+fn feeds_are_only_held_back_by_the_cooldown_once_shown() {
+    let config = Config::default();
+    let mut updater = updater();
+    let mut notifier = RecordingNotifier::default();
+
+    let update = updater
+        .process(vec![feed(1, 100, Some("fire"))], &config, &start())
+        .unwrap();
+
+    assert_eq!(update.notifs.len(), 1);
+
+    // The feed was never shown, so it shouldn't be on a cooldown yet
+    let mut update = updater
+        .process(vec![feed(1, 100, Some("fire"))], &config, &start())
+        .unwrap();
+
+    assert_eq!(update.notifs.len(), 1);
+    updater
+        .notify(&mut update, &config, &mut notifier, &start())
+        .unwrap();
+
+    let update = updater
+        .process(vec![feed(1, 100, Some("fire"))], &config, &start())
+        .unwrap();
+
+    assert!(update.notifs.is_empty());
+    assert_eq!(notifier.shown, vec![1]);
+}
+
+#[test]
+fn muted_feeds_are_not_shown() {
+    let config = Config::default();
+    let mut updater = updater();
+
+    updater.mute(1, 1).unwrap();
+
+    let update = updater
+        .process(vec![feed(1, 100, Some("fire"))], &config, &Utc::now())
+        .unwrap();
+
+    assert!(update.notifs.is_empty());
+
+    updater.unmute(1).unwrap();
+
+    let update = updater
+        .process(vec![feed(1, 100, Some("fire"))], &config, &Utc::now())
+        .unwrap();
+
+    assert_eq!(update.notifs.len(), 1);
+}
+
+#[test]
+fn quiet_hours_queue_feeds_until_they_end() {
+    let mut config = config(concat!(
+        "[quiet_hours]\n",
+        "times = [\"00:00-12:00\", \"12:00-00:00\"]\n",
+        "break_through_alerts = false\n",
+    ));
+
+    let mut updater = updater();
+    let mut notifier = RecordingNotifier::default();
+
+    let mut update = spike(&mut updater, &config, 1);
+    assert_eq!(update.notifs.len(), 1);
+
+    let shown = updater
+        .notify(&mut update, &config, &mut notifier, &start())
+        .unwrap();
+
+    assert!(shown.is_empty());
+    assert!(notifier.shown.is_empty());
+    assert!(!update.feeds[0].notified);
+    assert!(saved_notified(&updater).iter().all(|&notified| !notified));
+    assert_eq!(updater.num_queued(), 1);
+
+    // The queue shouldn't be flushed while quiet hours are still active
+    updater
+        .flush_quiet_queue(&config, &mut notifier, &start())
+        .unwrap();
+    assert!(notifier.summaries.is_empty());
+
+    config.quiet_hours.times.clear();
+    updater
+        .flush_quiet_queue(&config, &mut notifier, &start())
+        .unwrap();
+
+    assert_eq!(notifier.summaries, vec![vec![1]]);
+    assert_eq!(updater.num_queued(), 0);
+}
+
+#[test]
+fn queued_feeds_are_only_held_back_by_the_cooldown_once_shown() {
+    let mut config = config(concat!(
+        "[quiet_hours]\n",
+        "times = [\"00:00-12:00\", \"12:00-00:00\"]\n",
+        "break_through_alerts = false\n",
+    ));
+
+    let mut updater = updater();
+    let mut notifier = RecordingNotifier::default();
+
+    let mut update = updater
+        .process(vec![feed(1, 100, Some("fire"))], &config, &start())
+        .unwrap();
+
+    updater
+        .notify(&mut update, &config, &mut notifier, &start())
+        .unwrap();
+
+    // The queue may never be shown, such as when --once exits, so the feed has to be picked again
+    let mut update = updater
+        .process(vec![feed(1, 100, Some("fire"))], &config, &start())
+        .unwrap();
+
+    assert_eq!(update.notifs.len(), 1);
+
+    updater
+        .notify(&mut update, &config, &mut notifier, &start())
+        .unwrap();
+
+    assert_eq!(updater.num_queued(), 1);
+
+    config.quiet_hours.times.clear();
+    updater
+        .flush_quiet_queue(&config, &mut notifier, &start())
+        .unwrap();
+
+    let update = updater
+        .process(vec![feed(1, 100, Some("fire"))], &config, &start())
+        .unwrap();
+
+    assert!(update.notifs.is_empty());
+}
+
+#[test]
+fn suppressed_feeds_are_not_held_back_by_the_cooldown() {
+    let config = config(concat!(
+        "[quiet_hours]\n",
+        "mode = \"suppress\"\n",
+        "times = [\"00:00-12:00\", \"12:00-00:00\"]\n",
+        "break_through_alerts = false\n",
+    ));
+
+    let mut updater = updater();
+    let mut notifier = RecordingNotifier::default();
+
+    let mut update = updater
+        .process(vec![feed(1, 100, Some("fire"))], &config, &start())
+        .unwrap();
+
+    updater
+        .notify(&mut update, &config, &mut notifier, &start())
+        .unwrap();
+
+    assert!(notifier.shown.is_empty());
+    assert_eq!(updater.num_queued(), 0);
+
+    // Suppressed feeds never reached the user, so they can be shown again
+    let update = updater
+        .process(vec![feed(1, 100, Some("fire"))], &config, &start())
+        .unwrap();
+
+    assert_eq!(update.notifs.len(), 1);
+}
+
+#[test]
+fn stats_carry_over_between_updaters() {
+    let db = TempDatabase::new("carry-over");
+    let config = Config::default();
+
+    // Each update runs with a fresh updater, like separate runs of --once
+    for i in 0..3 {
+        let update = db
+            .updater()
+            .process(vec![feed(1, 100, None)], &config, &(start() + step() * i))
+            .unwrap();
+
+        assert!(update.notifs.is_empty());
+    }
+
+    let mut updater = db.updater();
+
+    let update = updater
+        .process(vec![feed(1, 200, None)], &config, &(start() + step() * 3))
+        .unwrap();
+
+    assert_eq!(update.notifs.len(), 1);
+    // The average was built up by the earlier updates rather than starting over from the current listeners
+    assert!(update.feeds[0].average < 150.0);
+
+    let mut updater = db.updater();
+
+    updater
+        .process(vec![feed(1, 300, None)], &config, &(start() + step() * 4))
+        .unwrap();
+
+    let (_, stats) = updater.stats().next().unwrap();
+    assert_eq!(stats.spike_count, 2);
+}
+
+#[test]
+fn reset_stats_removes_saved_stats() {
+    let db = TempDatabase::new("reset");
+    let config = Config::default();
+
+    let mut updater = db.updater();
+    spike(&mut updater, &config, 1);
+    updater.reset_stats().unwrap();
+
+    let mut updater = db.updater();
+
+    updater
+        .process(vec![feed(1, 300, None)], &config, &(start() + step() * 4))
+        .unwrap();
+
+    // The spike from before the reset isn't counted
+    let (_, stats) = updater.stats().next().unwrap();
+    assert_eq!(stats.spike_count, 1);
+}